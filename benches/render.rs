@@ -0,0 +1,76 @@
+//! Render latency benchmarks
+//!
+//! Each document size is rendered two ways: `per_call` builds a fresh highlighter for
+//! every render (the behaviour before the highlighter was shared, including re-compiling
+//! the syntaxes each code block uses), and `shared` goes through `convert_markdown`.
+//!
+//! Run with `cargo bench --bench render`.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use markdown_viewer::markdown::{
+    build_highlighter, convert_markdown, convert_markdown_with_highlighter, highlighter,
+};
+
+/// A short note with a single code block
+fn small_document() -> String {
+    "# Notes\n\nA short paragraph with **bold** and `code`.\n\n```rust\nfn main() {\n    println!(\"hi\");\n}\n```\n"
+        .to_string()
+}
+
+/// A long README-style document with many tables and highlighted code blocks
+fn large_document() -> String {
+    let section = r#"
+## Section {n}
+
+Some prose with *emphasis*, a [link](https://example.com) and ~~strikethrough~~.
+
+- [x] Done item
+- [ ] Open item
+
+| Column A | Column B |
+|----------|----------|
+| {n}      | value    |
+
+```rust
+fn section_{n}(input: &[u32]) -> u32 {
+    input.iter().filter(|v| **v % 2 == 0).sum()
+}
+```
+
+```python
+def section_{n}(values):
+    return sum(v for v in values if v % 2 == 0)
+```
+
+```javascript
+const section{n} = (values) => values.filter((v) => v % 2 === 0).reduce((a, b) => a + b, 0);
+```
+"#;
+
+    let mut doc = String::from("# Large document\n");
+    for n in 0..200 {
+        doc.push_str(&section.replace("{n}", &n.to_string()));
+    }
+    doc
+}
+
+fn bench_document(c: &mut Criterion, name: &str, doc: &str, sample_size: usize) {
+    // Build the shared highlighter up front so it isn't counted in the first sample
+    highlighter();
+
+    let mut group = c.benchmark_group(name);
+    group.sample_size(sample_size);
+    group.bench_function("per_call", |b| {
+        b.iter(|| convert_markdown_with_highlighter(black_box(doc), &build_highlighter()))
+    });
+    group.bench_function("shared", |b| b.iter(|| convert_markdown(black_box(doc))));
+    group.finish();
+}
+
+fn bench_render(c: &mut Criterion) {
+    bench_document(c, "render_small", &small_document(), 100);
+    bench_document(c, "render_large", &large_document(), 10);
+}
+
+criterion_group!(benches, bench_render);
+criterion_main!(benches);
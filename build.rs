@@ -0,0 +1,13 @@
+//! Build script: bundles mermaid.js into the binary when it has been vendored into
+//! `assets/` (see `scripts/fetch-mermaid.sh`)
+
+use std::path::Path;
+
+fn main() {
+    println!("cargo::rustc-check-cfg=cfg(mdview_mermaid)");
+    println!("cargo::rerun-if-changed=assets");
+
+    if Path::new("assets/mermaid.min.js").is_file() {
+        println!("cargo::rustc-cfg=mdview_mermaid");
+    }
+}
@@ -0,0 +1,24 @@
+#!/usr/bin/env bash
+set -euo pipefail
+
+# Downloads mermaid.js into assets/ so the next build bundles it and renders
+# ```mermaid diagrams offline. Without it, diagrams are shown as source.
+
+SCRIPT_DIR="$(cd "$(dirname "$0")" && pwd)"
+ROOT_DIR="$(cd "$SCRIPT_DIR/.." && pwd)"
+ASSETS_DIR="$ROOT_DIR/assets"
+
+VERSION="${1:-11.4.1}"
+TARBALL_URL="https://registry.npmjs.org/mermaid/-/mermaid-$VERSION.tgz"
+
+tmp_dir="$(mktemp -d)"
+trap 'rm -rf "$tmp_dir"' EXIT
+
+echo "Downloading mermaid $VERSION..."
+curl -fsSL "$TARBALL_URL" -o "$tmp_dir/mermaid.tgz"
+tar -xzf "$tmp_dir/mermaid.tgz" -C "$tmp_dir" package/dist/mermaid.min.js package/LICENSE
+
+cp "$tmp_dir/package/dist/mermaid.min.js" "$ASSETS_DIR/mermaid.min.js"
+cp "$tmp_dir/package/LICENSE" "$ASSETS_DIR/MERMAID-LICENSE"
+
+echo "Saved $ASSETS_DIR/mermaid.min.js; rebuild mdview to bundle it"
@@ -0,0 +1,107 @@
+/// Module for static page assets embedded in the binary
+use crate::server::respond_not_found;
+use tiny_http::{Header, Request, Response};
+
+/// URL prefix every embedded asset is served under
+///
+/// The prefix is absolute so pages find their assets wherever the document itself is
+/// mounted (`/`, `/doc/<id>/`, or a path inside a served directory).
+pub const ASSET_PREFIX: &str = "/__mdview/assets/";
+
+/// A file compiled into the binary and served to the page
+pub struct Asset {
+    pub name: &'static str,
+    pub content_type: &'static str,
+    pub data: &'static [u8],
+}
+
+/// Every embedded asset, looked up by name
+const ASSETS: &[Asset] = &[
+    // Adapted from github-markdown-css 5.5.1 (MIT, see assets/GITHUB-MARKDOWN-CSS-LICENSE)
+    Asset {
+        name: "github-markdown.css",
+        content_type: "text/css; charset=utf-8",
+        data: include_bytes!("../assets/github-markdown.css"),
+    },
+    // KaTeX 0.16.4 (MIT, see assets/KATEX-LICENSE), loaded by pages that contain math
+    Asset {
+        name: "katex.min.js",
+        content_type: "text/javascript; charset=utf-8",
+        data: include_bytes!("../assets/katex.min.js"),
+    },
+];
+
+/// mermaid.js, bundled only if it was vendored into `assets/` before building
+/// (see `scripts/fetch-mermaid.sh`)
+#[cfg(mdview_mermaid)]
+static MERMAID: Option<Asset> = Some(Asset {
+    name: "mermaid.min.js",
+    content_type: "text/javascript; charset=utf-8",
+    data: include_bytes!("../assets/mermaid.min.js"),
+});
+
+#[cfg(not(mdview_mermaid))]
+static MERMAID: Option<Asset> = None;
+
+/// Returns the URL an asset is served at
+pub fn asset_url(name: &str) -> String {
+    format!("{}{}", ASSET_PREFIX, name)
+}
+
+/// Looks up an embedded asset by name
+pub fn find_asset(name: &str) -> Option<&'static Asset> {
+    ASSETS
+        .iter()
+        .chain(MERMAID.as_ref())
+        .find(|asset| asset.name == name)
+}
+
+/// Checks whether a request path points at an embedded asset
+pub fn is_asset_request(path: &str) -> bool {
+    path.starts_with(ASSET_PREFIX)
+}
+
+/// Serves the embedded asset named by the request path, or a 404
+///
+/// # Arguments
+///
+/// * `request` - The incoming HTTP request
+/// * `path` - The request path, starting with `ASSET_PREFIX`
+pub fn serve_asset(request: Request, path: &str) {
+    let asset = match path.strip_prefix(ASSET_PREFIX).and_then(find_asset) {
+        Some(asset) => asset,
+        None => return respond_not_found(request),
+    };
+
+    let response = Response::from_data(asset.data)
+        .with_header(
+            Header::from_bytes(&b"Content-Type"[..], asset.content_type.as_bytes()).unwrap(),
+        )
+        .with_header(Header::from_bytes(&b"Cache-Control"[..], &b"max-age=3600"[..]).unwrap());
+    let _ = request.respond(response);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_asset_lookup() {
+        let css = find_asset("github-markdown.css").unwrap();
+        assert!(css.content_type.starts_with("text/css"));
+        assert!(String::from_utf8_lossy(css.data).contains(".markdown-body"));
+        assert!(find_asset("../Cargo.toml").is_none());
+
+        let katex = find_asset("katex.min.js").unwrap();
+        assert!(katex.content_type.starts_with("text/javascript"));
+        assert!(String::from_utf8_lossy(katex.data).contains("katex"));
+    }
+
+    #[test]
+    fn test_asset_url() {
+        let url = asset_url("github-markdown.css");
+        assert_eq!(url, "/__mdview/assets/github-markdown.css");
+        assert!(is_asset_request(&url));
+        assert!(!is_asset_request("/doc/readme-123abc/"));
+    }
+}
@@ -0,0 +1,642 @@
+/// Module for loading the user and project configuration files
+use crate::markdown::Extensions;
+use crate::theme::{DEFAULT_DARK_THEME, DEFAULT_LIGHT_THEME};
+use crate::watcher::DEFAULT_DEBOUNCE;
+use directories::ProjectDirs;
+use serde::{Deserialize, Serialize};
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use toml::{Table, Value};
+
+/// Name of the per-project config file, looked up from the working directory upwards
+pub const PROJECT_CONFIG_FILE: &str = ".mdview.toml";
+
+/// Settings that run a program, which a project config may not set
+///
+/// A `.mdview.toml` can come with any cloned repository, so only the user config is
+/// trusted to name commands.
+const USER_ONLY_KEYS: &[(&str, &str)] = &[("browser", "command"), ("editor", "command")];
+
+/// Number of ports tried when only the first port of the range is given
+pub const DEFAULT_PORT_ATTEMPTS: u16 = 100;
+
+/// Longest debounce window accepted, in milliseconds
+pub const MAX_DEBOUNCE_MS: u64 = 10_000;
+
+#[derive(Debug)]
+pub enum ConfigError {
+    NoProjectDirs,
+    Io(PathBuf, std::io::Error),
+    Parse(PathBuf, toml::de::Error),
+    Invalid(toml::de::Error),
+    Serialize(toml::ser::Error),
+    PortRange(u16, u16),
+    Debounce(u64),
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::NoProjectDirs => write!(f, "Could not determine config directory"),
+            ConfigError::Io(path, e) => write!(f, "Could not read '{}': {}", path.display(), e),
+            ConfigError::Parse(path, e) => write!(f, "Invalid config '{}': {}", path.display(), e),
+            ConfigError::Invalid(e) => write!(f, "Invalid config: {}", e),
+            ConfigError::Serialize(e) => write!(f, "Could not serialize config: {}", e),
+            ConfigError::PortRange(start, end) => {
+                write!(f, "Invalid port range: {} is after {}", start, end)
+            }
+            ConfigError::Debounce(ms) => write!(
+                f,
+                "Invalid debounce_ms: {} is not between 1 and {}",
+                ms, MAX_DEBOUNCE_MS
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Effective settings, merged from the config files and command-line flags
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub theme: ThemeConfig,
+    pub markdown: Extensions,
+    pub browser: BrowserConfig,
+    pub editor: EditorConfig,
+    pub watch: WatchConfig,
+
+    /// Config files that were loaded, lowest precedence first
+    #[serde(skip)]
+    pub sources: Vec<PathBuf>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    /// Address the HTTP server listens on
+    pub bind: String,
+    /// First port to try
+    pub port_start: u16,
+    /// Last port to try if the earlier ones are taken (defaults to 99 after `port_start`)
+    pub port_end: u16,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ThemeConfig {
+    /// Code highlighting theme for light pages: a bundled name or a `.tmTheme` path
+    pub light: String,
+    /// Code highlighting theme for dark pages: a bundled name or a `.tmTheme` path
+    pub dark: String,
+    /// Extra stylesheet added to every page after the built-in styles
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub custom_css: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BrowserConfig {
+    /// Whether to open the page in a browser when serving starts
+    pub open: bool,
+    /// Command used instead of the system default, e.g. `firefox --new-window {url}`
+    ///
+    /// `{url}` is replaced with the page URL; if absent, the URL is appended. Arguments
+    /// are split and quoted as in a shell.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EditorConfig {
+    /// Command that opens a file at a line, e.g. `code -g {file}:{line}`
+    ///
+    /// `{file}` and `{line}` are replaced with the document's path and the line that was
+    /// clicked; if `{file}` is absent, the path is appended. Arguments are split and
+    /// quoted as in a shell.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WatchConfig {
+    /// How long a file must stay unchanged after a save before the page reloads
+    pub debounce_ms: u64,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            bind: "127.0.0.1".to_string(),
+            port_start: 6914,
+            port_end: 6914 + DEFAULT_PORT_ATTEMPTS - 1,
+        }
+    }
+}
+
+impl Default for ThemeConfig {
+    fn default() -> Self {
+        Self {
+            light: DEFAULT_LIGHT_THEME.to_string(),
+            dark: DEFAULT_DARK_THEME.to_string(),
+            custom_css: None,
+        }
+    }
+}
+
+impl Default for BrowserConfig {
+    fn default() -> Self {
+        Self {
+            open: true,
+            command: None,
+        }
+    }
+}
+
+impl Default for WatchConfig {
+    fn default() -> Self {
+        Self {
+            debounce_ms: DEFAULT_DEBOUNCE.as_millis() as u64,
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Number of ports in the configured range
+    pub fn port_attempts(&self) -> u16 {
+        self.port_end
+            .saturating_sub(self.port_start)
+            .saturating_add(1)
+    }
+}
+
+impl WatchConfig {
+    /// The debounce window passed to the file watchers
+    pub fn debounce(&self) -> Duration {
+        Duration::from_millis(self.debounce_ms)
+    }
+}
+
+impl BrowserConfig {
+    /// Builds the program and arguments that open `url` with the configured command
+    ///
+    /// The command is split like a shell would, so arguments containing spaces can be
+    /// quoted, as for the editor command.
+    ///
+    /// # Returns
+    ///
+    /// None if no command is configured (or it's blank), meaning the system default is used
+    ///
+    /// # Errors
+    ///
+    /// Returns a message if the command can't be split
+    pub fn command_for(&self, url: &str) -> Result<Option<Vec<String>>, String> {
+        let Some(command) = self.command.as_deref() else {
+            return Ok(None);
+        };
+        let mut args = shell_words::split(command)
+            .map_err(|e| format!("Invalid browser command '{}': {}", command, e))?;
+        if args.is_empty() {
+            return Ok(None);
+        }
+        if args.iter().any(|arg| arg.contains("{url}")) {
+            for arg in &mut args {
+                *arg = arg.replace("{url}", url);
+            }
+        } else {
+            args.push(url.to_string());
+        }
+        Ok(Some(args))
+    }
+}
+
+impl EditorConfig {
+    /// Builds the program and arguments that open `file` at `line` with the configured
+    /// command
+    ///
+    /// The command is split like a shell would, so arguments containing spaces can be
+    /// quoted, e.g. `"/Applications/Sublime Text.app/Contents/SharedSupport/bin/subl"`.
+    ///
+    /// # Errors
+    ///
+    /// Returns a message if no command is configured or it can't be split
+    pub fn command_for(&self, file: &Path, line: usize) -> Result<Vec<String>, String> {
+        let command = self.command.as_deref().unwrap_or_default();
+        let args = shell_words::split(command)
+            .map_err(|e| format!("Invalid editor command '{}': {}", command, e))?;
+        if args.is_empty() {
+            return Err(
+                "No editor is configured; set `command` under [editor] in the config or pass --editor"
+                    .to_string(),
+            );
+        }
+
+        let file = file.to_string_lossy();
+        let line = line.to_string();
+        let mut args: Vec<String> = args
+            .iter()
+            .map(|arg| arg.replace("{file}", &file).replace("{line}", &line))
+            .collect();
+        if !command.contains("{file}") {
+            args.push(file.into_owned());
+        }
+        Ok(args)
+    }
+}
+
+impl Config {
+    /// Loads the user config and the nearest project config
+    ///
+    /// Settings in `.mdview.toml` take precedence over `config.toml`; missing files are
+    /// skipped and unset keys keep their defaults.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a config file exists but can't be read or parsed
+    pub fn load() -> Result<Self, ConfigError> {
+        let mut paths = Vec::new();
+        if let Ok(path) = get_config_path() {
+            paths.push(path);
+        }
+        if let Some(path) = std::env::current_dir()
+            .ok()
+            .and_then(|dir| find_project_config(&dir))
+        {
+            paths.push(path);
+        }
+        Self::load_from(&paths)
+    }
+
+    /// Loads and merges the given config files, later files taking precedence
+    ///
+    /// Files that don't exist are skipped. Commands set in a project config
+    /// (`.mdview.toml`) are ignored with a warning.
+    pub fn load_from(paths: &[PathBuf]) -> Result<Self, ConfigError> {
+        let mut merged = Table::new();
+        let mut sources = Vec::new();
+
+        for path in paths {
+            if !path.is_file() {
+                continue;
+            }
+            let mut table = read_table(path)?;
+            if path.file_name() == Some(OsStr::new(PROJECT_CONFIG_FILE)) {
+                remove_user_only_keys(path, &mut table);
+            }
+            merge_tables(&mut merged, table);
+            sources.push(path.clone());
+        }
+
+        let end_is_set = merged
+            .get("server")
+            .and_then(Value::as_table)
+            .is_some_and(|server| server.contains_key("port_end"));
+
+        let mut config: Config = Value::Table(merged)
+            .try_into()
+            .map_err(ConfigError::Invalid)?;
+        config.sources = sources;
+
+        let server = &mut config.server;
+        if !end_is_set {
+            server.port_end = server.port_start.saturating_add(DEFAULT_PORT_ATTEMPTS - 1);
+        }
+        if server.port_end < server.port_start {
+            return Err(ConfigError::PortRange(server.port_start, server.port_end));
+        }
+        if !(1..=MAX_DEBOUNCE_MS).contains(&config.watch.debounce_ms) {
+            return Err(ConfigError::Debounce(config.watch.debounce_ms));
+        }
+
+        Ok(config)
+    }
+
+    /// Renders the config as TOML
+    pub fn to_toml(&self) -> Result<String, ConfigError> {
+        toml::to_string_pretty(self).map_err(ConfigError::Serialize)
+    }
+}
+
+/// Returns mdview's config directory (`~/.config/mdview` on Linux)
+pub fn get_config_dir() -> Result<PathBuf, ConfigError> {
+    ProjectDirs::from("", "", "mdview")
+        .map(|dirs| dirs.config_dir().to_path_buf())
+        .ok_or(ConfigError::NoProjectDirs)
+}
+
+/// Returns the path of the user config file
+pub fn get_config_path() -> Result<PathBuf, ConfigError> {
+    Ok(get_config_dir()?.join("config.toml"))
+}
+
+/// Finds the nearest `.mdview.toml` in `start` or one of its ancestors
+pub fn find_project_config(start: &Path) -> Option<PathBuf> {
+    start
+        .ancestors()
+        .map(|dir| dir.join(PROJECT_CONFIG_FILE))
+        .find(|path| path.is_file())
+}
+
+/// Reads a config file into a table, resolving relative paths against its directory
+fn read_table(path: &Path) -> Result<Table, ConfigError> {
+    let source = fs::read_to_string(path).map_err(|e| ConfigError::Io(path.to_path_buf(), e))?;
+    let mut table: Table = source
+        .parse()
+        .map_err(|e| ConfigError::Parse(path.to_path_buf(), e))?;
+
+    let base = path.parent().unwrap_or(Path::new("."));
+    if let Some(Value::Table(theme)) = table.get_mut("theme") {
+        for key in ["light", "dark", "custom_css"] {
+            if let Some(Value::String(value)) = theme.get_mut(key) {
+                // Theme names are left alone; only values that look like paths are resolved
+                let is_path = key == "custom_css" || value.ends_with(".tmTheme");
+                if is_path && Path::new(value.as_str()).is_relative() {
+                    *value = base.join(value.as_str()).to_string_lossy().into_owned();
+                }
+            }
+        }
+    }
+
+    Ok(table)
+}
+
+/// Drops the settings in `USER_ONLY_KEYS` from a project config's table
+fn remove_user_only_keys(path: &Path, table: &mut Table) {
+    for (section, key) in USER_ONLY_KEYS {
+        let Some(Value::Table(section_table)) = table.get_mut(*section) else {
+            continue;
+        };
+        if section_table.remove(*key).is_some() {
+            eprintln!(
+                "Warning: ignoring `{}.{}` in '{}'; commands can only be set in the user config",
+                section,
+                key,
+                path.display()
+            );
+        }
+    }
+}
+
+/// Merges `overlay` into `base`, recursing into tables so sibling keys are kept
+fn merge_tables(base: &mut Table, overlay: Table) {
+    for (key, value) in overlay {
+        match (base.get_mut(&key), value) {
+            (Some(Value::Table(base_table)), Value::Table(overlay_table)) => {
+                merge_tables(base_table, overlay_table);
+            }
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Creates an empty scratch directory unique to this test
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("mdview-config-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_defaults_without_files() {
+        let config = Config::load_from(&[PathBuf::from("/nonexistent/config.toml")]).unwrap();
+        assert_eq!(config, Config::default());
+        assert_eq!(config.server.port_start, 6914);
+        assert_eq!(config.server.port_attempts(), 100);
+        assert!(config.browser.open);
+    }
+
+    #[test]
+    fn test_project_overrides_user() {
+        let dir = scratch_dir("merge");
+        let user = dir.join("config.toml");
+        let project = dir.join("project").join(PROJECT_CONFIG_FILE);
+        fs::create_dir_all(project.parent().unwrap()).unwrap();
+        fs::write(
+            &user,
+            "[server]\nport_start = 8000\nport_end = 8010\n\n[browser]\nopen = false\n",
+        )
+        .unwrap();
+        fs::write(
+            &project,
+            "[server]\nport_end = 8005\n\n[theme]\ncustom_css = \"docs.css\"\n",
+        )
+        .unwrap();
+
+        let config = Config::load_from(&[user.clone(), project.clone()]).unwrap();
+        assert_eq!(config.server.port_end, 8005);
+        // Keys the project file doesn't set still come from the user file
+        assert_eq!(config.server.port_start, 8000);
+        assert!(!config.browser.open);
+        assert_eq!(
+            config.theme.custom_css,
+            Some(project.parent().unwrap().join("docs.css"))
+        );
+        assert_eq!(config.sources, vec![user, project]);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_project_cannot_set_commands() {
+        let dir = scratch_dir("commands");
+        let user = dir.join("config.toml");
+        let project = dir.join("project").join(PROJECT_CONFIG_FILE);
+        fs::create_dir_all(project.parent().unwrap()).unwrap();
+        fs::write(
+            &user,
+            "[browser]\ncommand = \"firefox\"\n\n[editor]\ncommand = \"code -g {file}:{line}\"\n",
+        )
+        .unwrap();
+        fs::write(
+            &project,
+            "[browser]\nopen = false\ncommand = \"evil {url}\"\n\n[editor]\ncommand = \"evil {file}\"\n",
+        )
+        .unwrap();
+
+        let config = Config::load_from(&[user, project.clone()]).unwrap();
+        assert_eq!(config.browser.command.as_deref(), Some("firefox"));
+        assert_eq!(
+            config.editor.command.as_deref(),
+            Some("code -g {file}:{line}")
+        );
+        // Other settings in the same tables still apply
+        assert!(!config.browser.open);
+
+        // Without a user config the commands stay unset
+        let config = Config::load_from(&[project]).unwrap();
+        assert_eq!(config.browser.command, None);
+        assert_eq!(config.editor.command, None);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_debounce() {
+        let dir = scratch_dir("debounce");
+        let path = dir.join("config.toml");
+
+        fs::write(&path, "[watch]\ndebounce_ms = 250\n").unwrap();
+        let config = Config::load_from(std::slice::from_ref(&path)).unwrap();
+        assert_eq!(config.watch.debounce(), Duration::from_millis(250));
+
+        for ms in [0, MAX_DEBOUNCE_MS + 1] {
+            fs::write(&path, format!("[watch]\ndebounce_ms = {}\n", ms)).unwrap();
+            assert!(matches!(
+                Config::load_from(std::slice::from_ref(&path)),
+                Err(ConfigError::Debounce(value)) if value == ms
+            ));
+        }
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_port_range() {
+        let dir = scratch_dir("ports");
+        let path = dir.join("config.toml");
+
+        // Moving the start keeps the default number of ports
+        fs::write(&path, "[server]\nport_start = 9000\n").unwrap();
+        let config = Config::load_from(std::slice::from_ref(&path)).unwrap();
+        assert_eq!(config.server.port_end, 9099);
+
+        fs::write(&path, "[server]\nport_start = 9000\nport_end = 8000\n").unwrap();
+        assert!(matches!(
+            Config::load_from(&[path]),
+            Err(ConfigError::PortRange(9000, 8000))
+        ));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_unknown_key_is_an_error() {
+        let dir = scratch_dir("unknown");
+        let path = dir.join("config.toml");
+        fs::write(&path, "[server]\nprot = 1\n").unwrap();
+        assert!(matches!(
+            Config::load_from(&[path]),
+            Err(ConfigError::Invalid(_))
+        ));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_find_project_config_searches_upwards() {
+        let dir = scratch_dir("find");
+        let nested = dir.join("a").join("b");
+        fs::create_dir_all(&nested).unwrap();
+        fs::write(dir.join(PROJECT_CONFIG_FILE), "").unwrap();
+
+        assert_eq!(
+            find_project_config(&nested),
+            Some(dir.join(PROJECT_CONFIG_FILE))
+        );
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_browser_command() {
+        let mut browser = BrowserConfig::default();
+        assert_eq!(browser.command_for("http://localhost:6914"), Ok(None));
+
+        browser.command = Some("firefox --new-window".to_string());
+        assert_eq!(
+            browser
+                .command_for("http://localhost:6914")
+                .unwrap()
+                .unwrap(),
+            vec!["firefox", "--new-window", "http://localhost:6914"]
+        );
+
+        browser.command = Some("open -a Safari {url}".to_string());
+        assert_eq!(
+            browser
+                .command_for("http://localhost:6914")
+                .unwrap()
+                .unwrap(),
+            vec!["open", "-a", "Safari", "http://localhost:6914"]
+        );
+
+        // Quoted paths with spaces stay one argument
+        browser.command = Some(
+            "\"/Applications/Google Chrome.app/Contents/MacOS/Google Chrome\" --new-window"
+                .to_string(),
+        );
+        assert_eq!(
+            browser
+                .command_for("http://localhost:6914")
+                .unwrap()
+                .unwrap(),
+            vec![
+                "/Applications/Google Chrome.app/Contents/MacOS/Google Chrome",
+                "--new-window",
+                "http://localhost:6914"
+            ]
+        );
+
+        browser.command = Some("\"unterminated --new-window".to_string());
+        assert!(browser
+            .command_for("http://localhost:6914")
+            .unwrap_err()
+            .contains("Invalid browser command"));
+    }
+
+    #[test]
+    fn test_editor_command() {
+        let mut editor = EditorConfig::default();
+        let file = Path::new("/docs/my notes.md");
+        assert!(editor
+            .command_for(file, 12)
+            .unwrap_err()
+            .contains("No editor"));
+
+        editor.command = Some("code -g {file}:{line}".to_string());
+        assert_eq!(
+            editor.command_for(file, 12).unwrap(),
+            vec!["code", "-g", "/docs/my notes.md:12"]
+        );
+
+        editor.command = Some("emacsclient -n +{line}".to_string());
+        assert_eq!(
+            editor.command_for(file, 3).unwrap(),
+            vec!["emacsclient", "-n", "+3", "/docs/my notes.md"]
+        );
+
+        // Quoted programs and arguments stay whole
+        editor.command =
+            Some(r#""/Applications/Sublime Text.app/subl" '{file}:{line}'"#.to_string());
+        assert_eq!(
+            editor.command_for(file, 7).unwrap(),
+            vec!["/Applications/Sublime Text.app/subl", "/docs/my notes.md:7"]
+        );
+
+        editor.command = Some("code \"{file}".to_string());
+        assert!(editor
+            .command_for(file, 1)
+            .unwrap_err()
+            .contains("Invalid editor command"));
+    }
+
+    #[test]
+    fn test_round_trips_through_toml() {
+        let config = Config::default();
+        let parsed: Config = toml::from_str(&config.to_toml().unwrap()).unwrap();
+        assert_eq!(parsed, config);
+    }
+}
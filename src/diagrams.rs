@@ -0,0 +1,240 @@
+/// Module for rendering diagram fences to SVG with locally installed tools
+use crate::server::hash_bytes;
+use crate::template::escape_html;
+use std::collections::HashMap;
+use std::io::{ErrorKind, Read, Write};
+use std::process::{Command, Stdio};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+/// How long a renderer may run before it is killed
+pub const RENDER_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Most rendered diagrams kept in memory; the cache is emptied when it fills up
+const CACHE_LIMIT: usize = 256;
+
+/// Rendered SVG by tool and source hash
+///
+/// Errors aren't kept, so a tool that was missing or timed out is tried again on the
+/// next render.
+type RenderCache = HashMap<(DiagramTool, u64), String>;
+
+/// Rendered diagrams, so unchanged diagrams aren't re-rendered on every reload
+static CACHE: OnceLock<Mutex<RenderCache>> = OnceLock::new();
+
+/// An external program that turns diagram source into SVG
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DiagramTool {
+    Graphviz,
+    PlantUml,
+}
+
+impl DiagramTool {
+    /// Finds the tool for a fence language, if it is a diagram language
+    pub fn for_language(language: &str) -> Option<Self> {
+        match language {
+            "dot" | "graphviz" => Some(DiagramTool::Graphviz),
+            "plantuml" | "puml" => Some(DiagramTool::PlantUml),
+            _ => None,
+        }
+    }
+
+    /// Returns the command line that reads source on stdin and writes SVG to stdout
+    pub fn command(&self) -> &'static [&'static str] {
+        match self {
+            DiagramTool::Graphviz => &["dot", "-Tsvg"],
+            DiagramTool::PlantUml => &["plantuml", "-tsvg", "-pipe"],
+        }
+    }
+
+    /// Returns the name used in CSS classes and error messages
+    pub fn name(&self) -> &'static str {
+        match self {
+            DiagramTool::Graphviz => "graphviz",
+            DiagramTool::PlantUml => "plantuml",
+        }
+    }
+}
+
+/// Renders a diagram fence to HTML
+///
+/// On success this is the SVG in a container; otherwise it is an error box followed by
+/// the original source.
+///
+/// # Arguments
+///
+/// * `tool` - The renderer for the fence's language
+/// * `source` - The diagram source from the fence
+pub fn render_diagram_html(tool: DiagramTool, source: &str) -> String {
+    match render_diagram(tool, source) {
+        Ok(svg) => format!(
+            "<div class=\"diagram diagram-{}\">\n{}\n</div>\n",
+            tool.name(),
+            svg
+        ),
+        Err(message) => format!(
+            "<div class=\"diagram diagram-{}\">\n<div class=\"diagram-error\">{}</div>\n<pre class=\"diagram-source\"><code>{}</code></pre>\n</div>\n",
+            tool.name(),
+            escape_html(&message),
+            escape_html(source)
+        ),
+    }
+}
+
+/// Renders a diagram to SVG, reusing the SVG for source that was rendered before
+///
+/// # Errors
+///
+/// Returns a message saying why the diagram couldn't be rendered
+pub fn render_diagram(tool: DiagramTool, source: &str) -> Result<String, String> {
+    let key = (tool, hash_bytes(source.as_bytes()));
+    let cache = CACHE.get_or_init(|| Mutex::new(HashMap::new()));
+    if let Some(svg) = cache.lock().unwrap().get(&key) {
+        return Ok(svg.clone());
+    }
+
+    let result = run_with_timeout(tool.command(), source, RENDER_TIMEOUT).and_then(|output| {
+        let output = String::from_utf8_lossy(&output);
+        // Drop the XML prolog and doctype so the SVG can be inlined
+        match output.find("<svg") {
+            Some(start) => Ok(output[start..].trim_end().to_string()),
+            None => Err(format!("{} produced no SVG", tool.command()[0])),
+        }
+    })?;
+
+    let mut cache = cache.lock().unwrap();
+    if cache.len() >= CACHE_LIMIT {
+        cache.clear();
+    }
+    cache.insert(key, result.clone());
+    Ok(result)
+}
+
+/// Runs a command with `input` on stdin, killing it if it runs past `timeout`
+///
+/// # Returns
+///
+/// The command's stdout, or a message describing why it failed
+pub fn run_with_timeout(
+    command: &[&str],
+    input: &str,
+    timeout: Duration,
+) -> Result<Vec<u8>, String> {
+    let (program, args) = command.split_first().ok_or("Empty command")?;
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| match e.kind() {
+            ErrorKind::NotFound => format!("'{}' is not installed or not on PATH", program),
+            _ => format!("Could not run '{}': {}", program, e),
+        })?;
+
+    // Feed stdin and drain the output on their own threads so a chatty tool can't block
+    let mut stdin = child.stdin.take().unwrap();
+    let input = input.to_string();
+    std::thread::spawn(move || {
+        let _ = stdin.write_all(input.as_bytes());
+    });
+    let mut stdout = child.stdout.take().unwrap();
+    let stdout_reader = std::thread::spawn(move || {
+        let mut buf = Vec::new();
+        let _ = stdout.read_to_end(&mut buf);
+        buf
+    });
+    let mut stderr = child.stderr.take().unwrap();
+    let stderr_reader = std::thread::spawn(move || {
+        let mut buf = String::new();
+        let _ = stderr.read_to_string(&mut buf);
+        buf
+    });
+
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break status,
+            Ok(None) if Instant::now() < deadline => {
+                std::thread::sleep(Duration::from_millis(10));
+            }
+            Ok(None) => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(format!(
+                    "'{}' timed out after {}s",
+                    program,
+                    timeout.as_secs_f32()
+                ));
+            }
+            Err(e) => return Err(format!("Could not wait for '{}': {}", program, e)),
+        }
+    };
+
+    let stdout = stdout_reader.join().unwrap_or_default();
+    let stderr = stderr_reader.join().unwrap_or_default();
+    if !status.success() {
+        let detail = stderr.trim();
+        return Err(if detail.is_empty() {
+            format!("'{}' failed ({})", program, status)
+        } else {
+            format!("'{}' failed: {}", program, detail)
+        });
+    }
+    Ok(stdout)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fence_languages() {
+        assert_eq!(
+            DiagramTool::for_language("dot"),
+            Some(DiagramTool::Graphviz)
+        );
+        assert_eq!(
+            DiagramTool::for_language("plantuml"),
+            Some(DiagramTool::PlantUml)
+        );
+        assert_eq!(DiagramTool::for_language("rust"), None);
+    }
+
+    #[test]
+    fn test_run_with_timeout() {
+        let output = run_with_timeout(&["cat"], "<svg/>", RENDER_TIMEOUT).unwrap();
+        assert_eq!(output, b"<svg/>");
+
+        let started = Instant::now();
+        let err = run_with_timeout(&["sleep", "5"], "", Duration::from_millis(100)).unwrap_err();
+        assert!(err.contains("timed out"));
+        assert!(started.elapsed() < Duration::from_secs(2));
+
+        let err = run_with_timeout(
+            &["sh", "-c", "echo bad syntax >&2; exit 1"],
+            "",
+            RENDER_TIMEOUT,
+        )
+        .unwrap_err();
+        assert_eq!(err, "'sh' failed: bad syntax");
+
+        let err = run_with_timeout(&["mdview-no-such-tool"], "", RENDER_TIMEOUT).unwrap_err();
+        assert!(err.contains("not installed"));
+    }
+
+    #[test]
+    fn test_failed_render_shows_error_and_source() {
+        // Whether or not the tool is installed, invalid source must not render
+        let html = render_diagram_html(DiagramTool::Graphviz, "digraph { a -> <b> ");
+        assert!(html.contains("<div class=\"diagram-error\">"));
+        assert!(html.contains(
+            "<pre class=\"diagram-source\"><code>digraph { a -&gt; &lt;b&gt; </code></pre>"
+        ));
+
+        // The failure isn't cached, so it's retried once the source or tool is fixed
+        let key = (DiagramTool::Graphviz, hash_bytes(b"digraph { a -> <b> "));
+        let cache = CACHE.get().unwrap().lock().unwrap();
+        assert!(!cache.contains_key(&key));
+    }
+}
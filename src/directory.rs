@@ -0,0 +1,482 @@
+/// Module for serving a directory tree of markdown documents
+use crate::assets::{is_asset_request, serve_asset};
+use crate::config::EditorConfig;
+use crate::server::{
+    is_event_stream_request, is_image_request, resolve_file, respond_not_found, serve_image,
+    split_query, stream_events, Broadcaster, MarkdownServer, ServerEvent, WatchedDocument,
+};
+use crate::template::{build_html_page, escape_html};
+use crate::watcher::watch_tree;
+use crossbeam_channel::unbounded;
+use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tiny_http::{Header, Request, Response, Server};
+use walkdir::{DirEntry, WalkDir};
+
+/// File extensions treated as markdown documents
+pub const MARKDOWN_EXTENSIONS: &[&str] = &["md", "markdown", "mdown", "mkd"];
+
+/// Characters escaped when turning a relative file path into a link
+const PATH_ENCODE_SET: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'`');
+
+/// Checks whether a path has a markdown file extension
+pub fn is_markdown_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| {
+            MARKDOWN_EXTENSIONS
+                .iter()
+                .any(|md| ext.eq_ignore_ascii_case(md))
+        })
+        .unwrap_or(false)
+}
+
+/// Lists the markdown files below `root`, relative to it and sorted
+///
+/// Hidden files and directories (such as `.git`) are skipped.
+pub fn markdown_files(root: &Path) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = WalkDir::new(root)
+        .into_iter()
+        .filter_entry(|entry| entry.depth() == 0 || !is_hidden(entry))
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_type().is_file() && is_markdown_file(entry.path()))
+        .filter_map(|entry| entry.path().strip_prefix(root).ok().map(Path::to_path_buf))
+        .collect();
+    files.sort();
+    files
+}
+
+/// Serves every markdown file below a root directory
+///
+/// `/` shows an index of the tree, and each document is served at its path relative
+/// to the root, so relative links between documents (`guide/setup.md`, `../README.md`)
+/// resolve in the browser without rewriting. Documents are rendered and watched the
+/// first time they are requested, and each page streams updates for its own file only.
+pub struct DirectoryServer {
+    root: PathBuf,
+    /// Opened documents keyed by their canonical path
+    documents: Mutex<HashMap<PathBuf, WatchedDocument>>,
+    /// Live updates for the index page
+    index_hub: Broadcaster<ServerEvent>,
+    /// The index body most recently published, to skip no-op updates
+    last_index: Mutex<String>,
+    /// How documents are opened in an editor from their pages
+    editor: EditorConfig,
+    /// Quiet period the file watchers wait for before reloading
+    debounce: Duration,
+}
+
+impl DirectoryServer {
+    /// Creates a server for the tree below `root`
+    ///
+    /// # Arguments
+    ///
+    /// * `root` - Canonical path of the directory to serve
+    /// * `editor` - Editor command that documents' pages can open their files with
+    /// * `debounce` - Quiet period the file watchers wait for before reloading
+    pub fn new(root: PathBuf, editor: EditorConfig, debounce: Duration) -> Self {
+        let server = Self {
+            root,
+            documents: Mutex::new(HashMap::new()),
+            index_hub: Broadcaster::new(),
+            last_index: Mutex::new(String::new()),
+            editor,
+            debounce,
+        };
+        *server.last_index.lock().unwrap() = server.index_body();
+        server
+    }
+
+    /// Lists the markdown files in the tree, relative to the root and sorted
+    pub fn markdown_files(&self) -> Vec<PathBuf> {
+        markdown_files(&self.root)
+    }
+
+    /// Returns the root directory's name, used as the index title
+    fn title(&self) -> &str {
+        self.root
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or("Documents")
+    }
+
+    /// Renders the index of markdown files
+    ///
+    /// Links are relative so the index works wherever the tree is mounted.
+    fn index_body(&self) -> String {
+        let mut html = format!("<h1>{}</h1>\n", escape_html(self.title()));
+
+        let files = self.markdown_files();
+        if files.is_empty() {
+            html.push_str("<p>No markdown files found.</p>");
+            return html;
+        }
+
+        html.push_str("<ul>\n");
+        for file in files {
+            let display = file.to_string_lossy().replace('\\', "/");
+            let href = utf8_percent_encode(&display, PATH_ENCODE_SET).to_string();
+            html.push_str(&format!(
+                "<li><a href=\"{}\">{}</a></li>\n",
+                escape_html(&href),
+                escape_html(&display)
+            ));
+        }
+        html.push_str("</ul>");
+        html
+    }
+
+    /// Re-renders the index and pushes it to open index pages if it changed
+    pub fn publish_index_if_changed(&self) {
+        let body = self.index_body();
+        {
+            let mut last = self.last_index.lock().unwrap();
+            if *last == body {
+                return;
+            }
+            *last = body.clone();
+        }
+        self.index_hub.publish(ServerEvent::Update {
+            html: body,
+            title: None,
+            toc: None,
+        });
+    }
+
+    /// Keeps the index page up to date as markdown files are added, removed or renamed
+    pub fn spawn_index_watcher(self: &Arc<Self>) {
+        let (change_tx, change_rx) = unbounded();
+        let root = self.root.clone();
+        let debounce = self.debounce;
+        std::thread::spawn(move || {
+            if let Err(e) = watch_tree(root, change_tx, debounce) {
+                eprintln!("Directory watcher error: {}", e);
+            }
+        });
+
+        let server = Arc::clone(self);
+        std::thread::spawn(move || {
+            for () in change_rx {
+                server.publish_index_if_changed();
+            }
+        });
+    }
+
+    /// Returns the server for the markdown file at `url_path`, opening it on first use
+    ///
+    /// Returns None if the path doesn't name a markdown file inside the root.
+    fn document(&self, url_path: &str) -> Option<Arc<MarkdownServer>> {
+        let file_path = resolve_file(&self.root, url_path)?;
+        if !is_markdown_file(&file_path) {
+            return None;
+        }
+
+        let mut documents = self.documents.lock().unwrap();
+        if let Some(document) = documents.get(&file_path) {
+            return Some(Arc::clone(&document.server));
+        }
+
+        match WatchedDocument::open(&file_path, self.debounce) {
+            Ok(document) => {
+                document.server.set_editor(self.editor.clone());
+                let server = Arc::clone(&document.server);
+                documents.insert(file_path, document);
+                Some(server)
+            }
+            Err(message) => {
+                eprintln!("{}", message);
+                None
+            }
+        }
+    }
+
+    /// Routes a request to the index, a document, or an image in the tree
+    ///
+    /// # Arguments
+    ///
+    /// * `request` - The incoming HTTP request
+    pub fn handle_request(&self, request: Request) {
+        let url = request.url().to_string();
+        self.handle_path(request, &url);
+    }
+
+    /// Handles an HTTP request as if it were made for `url`, relative to the root
+    pub fn handle_path(&self, request: Request, url: &str) {
+        let (path, query) = split_query(url);
+
+        if is_asset_request(path) {
+            serve_asset(request, path);
+        } else if path == "/" {
+            if is_event_stream_request(url) {
+                stream_events(request, &self.index_hub);
+            } else {
+                // Rescan so the listing is current even if the tree watcher missed something
+                self.publish_index_if_changed();
+                let body = self.last_index.lock().unwrap().clone();
+                let response = Response::from_string(build_html_page(&body, self.title()))
+                    .with_header(
+                        Header::from_bytes(&b"Content-Type"[..], &b"text/html; charset=utf-8"[..])
+                            .unwrap(),
+                    );
+                let _ = request.respond(response);
+            }
+        } else if is_image_request(path) {
+            serve_image(request, &self.root, path);
+        } else if let Some((document, endpoint)) = path
+            .split_once("/api/")
+            .filter(|(document, _)| is_markdown_file(Path::new(document)))
+        {
+            // A document's API lives below its page, e.g. `/guide.md/api/cursor`
+            match self.document(document) {
+                Some(server) => server.handle_path(request, &format!("/api/{}", endpoint)),
+                None => respond_not_found(request),
+            }
+        } else {
+            match self.document(path) {
+                Some(server) => {
+                    // The document server sees itself mounted at this page's URL
+                    let rest = match query {
+                        Some(query) => format!("/?{}", query),
+                        None => "/".to_string(),
+                    };
+                    server.handle_path(request, &rest);
+                }
+                None => respond_not_found(request),
+            }
+        }
+    }
+
+    /// Runs the HTTP server, blocking indefinitely
+    ///
+    /// # Arguments
+    ///
+    /// * `bind` - The address to listen on
+    /// * `port` - The port number to listen on
+    pub fn run(
+        self: &Arc<Self>,
+        bind: &str,
+        port: u16,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let http_server = Server::http((bind, port))?;
+        self.spawn_index_watcher();
+
+        for request in http_server.incoming_requests() {
+            let server = Arc::clone(self);
+
+            // Spawn a thread for each request
+            std::thread::spawn(move || {
+                server.handle_request(request);
+            });
+        }
+
+        Ok(())
+    }
+}
+
+/// Checks whether a directory entry is hidden (its name starts with a dot)
+fn is_hidden(entry: &DirEntry) -> bool {
+    entry
+        .file_name()
+        .to_str()
+        .map(|name| name.starts_with('.'))
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::watcher::DEFAULT_DEBOUNCE;
+    use std::fs;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::time::Instant;
+
+    /// Creates a scratch doc tree: README.md linking to guide/setup.md, plus an image
+    fn scratch_tree(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("mdview-dir-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("guide")).unwrap();
+        fs::create_dir_all(dir.join(".git")).unwrap();
+        fs::write(
+            dir.join("README.md"),
+            "# Readme\n\n[Setup](guide/setup.md)\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("guide/setup.md"),
+            "# Setup guide\n\n![shot](shot.png)\n",
+        )
+        .unwrap();
+        fs::write(dir.join("guide/shot.png"), b"\x89PNG").unwrap();
+        fs::write(dir.join("notes.txt"), "not markdown").unwrap();
+        fs::write(dir.join(".git/HEAD.md"), "# Hidden").unwrap();
+        dir.canonicalize().unwrap()
+    }
+
+    /// Starts a directory server on a free port
+    fn start_server(root: &Path) -> u16 {
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let server = Arc::new(DirectoryServer::new(
+            root.to_path_buf(),
+            EditorConfig::default(),
+            DEFAULT_DEBOUNCE,
+        ));
+        std::thread::spawn(move || server.run("127.0.0.1", port));
+        port
+    }
+
+    fn connect(port: u16) -> TcpStream {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            match TcpStream::connect(("127.0.0.1", port)) {
+                Ok(stream) => return stream,
+                Err(e) if Instant::now() > deadline => panic!("Server never came up: {}", e),
+                Err(_) => std::thread::sleep(Duration::from_millis(20)),
+            }
+        }
+    }
+
+    /// Performs a GET request and returns the raw HTTP response
+    fn get(port: u16, path: &str) -> String {
+        let mut stream = connect(port);
+        write!(
+            stream,
+            "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+            path
+        )
+        .unwrap();
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).unwrap();
+        String::from_utf8_lossy(&response).into_owned()
+    }
+
+    #[test]
+    fn test_is_markdown_file() {
+        assert!(is_markdown_file(Path::new("a/README.md")));
+        assert!(is_markdown_file(Path::new("NOTES.Markdown")));
+        assert!(!is_markdown_file(Path::new("notes.txt")));
+        assert!(!is_markdown_file(Path::new("md")));
+    }
+
+    #[test]
+    fn test_lists_markdown_files_skipping_hidden() {
+        let root = scratch_tree("list");
+        let server = DirectoryServer::new(root.clone(), EditorConfig::default(), DEFAULT_DEBOUNCE);
+
+        assert_eq!(
+            server.markdown_files(),
+            vec![PathBuf::from("README.md"), PathBuf::from("guide/setup.md")]
+        );
+
+        let index = server.index_body();
+        assert!(index.contains("<a href=\"guide/setup.md\">guide/setup.md</a>"));
+        assert!(!index.contains("notes.txt"));
+
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn test_serves_linked_documents() {
+        let root = scratch_tree("serve");
+        let port = start_server(&root);
+
+        assert!(get(port, "/").contains("href=\"README.md\""));
+
+        let readme = get(port, "/README.md");
+        assert!(readme.contains("Readme</h1>"));
+        assert!(readme.contains("href=\"guide/setup.md\""));
+
+        // The relative link from README.md resolves to the linked document, not README again
+        let setup = get(port, "/guide/setup.md");
+        assert!(setup.contains("Setup guide</h1>"));
+
+        // Images resolve relative to the document that references them
+        assert!(get(port, "/guide/shot.png").starts_with("HTTP/1.1 200"));
+
+        assert!(get(port, "/notes.txt").starts_with("HTTP/1.1 404"));
+        assert!(get(port, "/missing.md").starts_with("HTTP/1.1 404"));
+        assert!(get(port, "/../README.md").starts_with("HTTP/1.1 404"));
+
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn test_updates_reach_the_edited_document() {
+        let root = scratch_tree("reload");
+        let port = start_server(&root);
+
+        let mut stream = connect(port);
+        write!(
+            stream,
+            "GET /guide/setup.md?events HTTP/1.1\r\nHost: localhost\r\n\r\n"
+        )
+        .unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(10)))
+            .unwrap();
+        let mut reader = BufReader::new(stream);
+        // Skip the response headers
+        let mut line = String::new();
+        while line != "\r\n" {
+            line.clear();
+            reader.read_line(&mut line).unwrap();
+        }
+
+        // Give the document's watcher time to start before editing
+        std::thread::sleep(Duration::from_millis(300));
+        fs::write(root.join("guide/setup.md"), "# Setup guide, edited\n").unwrap();
+
+        loop {
+            line.clear();
+            reader.read_line(&mut line).unwrap();
+            if line.contains("\"type\":\"update\"") {
+                assert!(line.contains("Setup guide, edited"));
+                break;
+            }
+        }
+
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn test_document_api_is_mounted_below_its_page() {
+        let root = scratch_tree("api");
+        let port = start_server(&root);
+
+        let post = |path: &str| {
+            let body = "{\"line\": 1}";
+            let mut stream = connect(port);
+            write!(
+                stream,
+                "POST {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\
+                 Content-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+                path,
+                body.len(),
+                body
+            )
+            .unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        };
+        assert!(post("/guide/setup.md/api/cursor").starts_with("HTTP/1.1 204"));
+        assert!(post("/guide/missing.md/api/cursor").starts_with("HTTP/1.1 404"));
+
+        let _ = fs::remove_dir_all(&root);
+    }
+}
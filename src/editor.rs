@@ -0,0 +1,37 @@
+/// Module for opening a document's source in the user's editor
+use crate::config::EditorConfig;
+use std::io::ErrorKind;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// Opens a file at a line with an editor command
+///
+/// The editor is started in the background; it isn't waited for.
+///
+/// # Arguments
+///
+/// * `editor` - The editor settings to use
+/// * `file` - Path of the file to open
+/// * `line` - 1-based line to put the cursor on
+///
+/// # Errors
+///
+/// Returns a message if no editor is configured or the command can't be started
+pub fn open_in_editor(editor: &EditorConfig, file: &Path, line: usize) -> Result<(), String> {
+    let args = editor.command_for(file, line)?;
+    let (program, args) = args.split_first().expect("Editor commands aren't empty");
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .spawn()
+        .map_err(|e| match e.kind() {
+            ErrorKind::NotFound => format!("'{}' is not installed or not on PATH", program),
+            _ => format!("Could not run '{}': {}", program, e),
+        })?;
+
+    // Reap the editor (or the client that hands off to it) when it exits
+    std::thread::spawn(move || {
+        let _ = child.wait();
+    });
+    Ok(())
+}
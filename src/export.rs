@@ -0,0 +1,242 @@
+/// Module for exporting a document to a self-contained HTML file
+use crate::markdown::render_document;
+use crate::server::{get_content_type, page_title};
+use crate::template::build_standalone_page;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use percent_encoding::percent_decode_str;
+use regex::{Captures, Regex};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+#[derive(Debug)]
+pub enum ExportError {
+    Io(PathBuf, std::io::Error),
+}
+
+impl std::fmt::Display for ExportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExportError::Io(path, e) => write!(f, "Could not read '{}': {}", path.display(), e),
+        }
+    }
+}
+
+impl std::error::Error for ExportError {}
+
+/// Options for `export_document`
+#[derive(Debug, Clone)]
+pub struct ExportOptions {
+    /// Embed local images as data URIs instead of linking to them
+    pub inline_images: bool,
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        Self {
+            inline_images: true,
+        }
+    }
+}
+
+/// A finished export and anything that couldn't be included in it
+pub struct Export {
+    pub html: String,
+    /// Problems that didn't stop the export, such as images that couldn't be read
+    pub warnings: Vec<String>,
+}
+
+/// Renders a markdown file to a self-contained HTML page
+///
+/// # Arguments
+///
+/// * `file_path` - Path to the markdown file
+/// * `options` - What to embed in the page
+///
+/// # Errors
+///
+/// Returns an error if the file can't be read
+pub fn export_document(file_path: &Path, options: &ExportOptions) -> Result<Export, ExportError> {
+    let content =
+        fs::read_to_string(file_path).map_err(|e| ExportError::Io(file_path.to_path_buf(), e))?;
+    let rendered = render_document(&content);
+    let title = page_title(&rendered, file_path);
+
+    let mut warnings = Vec::new();
+    let body = if options.inline_images {
+        let base_dir = file_path.parent().unwrap_or(Path::new("."));
+        inline_images(&rendered.html, base_dir, &mut warnings)
+    } else {
+        rendered.html
+    };
+
+    Ok(Export {
+        html: build_standalone_page(&body, &title),
+        warnings,
+    })
+}
+
+/// Matches the `src` attribute of an `<img>` tag, single- or double-quoted
+fn img_src_pattern() -> &'static Regex {
+    static PATTERN: OnceLock<Regex> = OnceLock::new();
+    PATTERN.get_or_init(|| {
+        Regex::new(r#"(<img\b[^>]*?\bsrc=)(?:"([^"]*)"|'([^']*)')"#).expect("Valid image pattern")
+    })
+}
+
+/// Replaces the `src` of every local image with a data URI of the file's contents
+///
+/// Remote images, data URIs and images that can't be read are left as they are; the
+/// latter are reported in `warnings`.
+///
+/// # Arguments
+///
+/// * `html` - Rendered document HTML
+/// * `base_dir` - Directory relative image paths are resolved against
+/// * `warnings` - Collects a message for each image that couldn't be embedded
+pub fn inline_images(html: &str, base_dir: &Path, warnings: &mut Vec<String>) -> String {
+    img_src_pattern()
+        .replace_all(html, |caps: &Captures| {
+            let src = caps
+                .get(2)
+                .or_else(|| caps.get(3))
+                .map_or("", |m| m.as_str());
+            match local_file_path(src, base_dir) {
+                Some(path) => match fs::read(&path) {
+                    Ok(data) => format!(
+                        "{}\"data:{};base64,{}\"",
+                        &caps[1],
+                        get_content_type(&path),
+                        STANDARD.encode(data)
+                    ),
+                    Err(e) => {
+                        warnings.push(format!("Could not embed image '{}': {}", src, e));
+                        caps[0].to_string()
+                    }
+                },
+                None => caps[0].to_string(),
+            }
+        })
+        .into_owned()
+}
+
+/// Resolves a link or image URL to a file path, or None if it isn't a local path
+pub(crate) fn local_file_path(src: &str, base_dir: &Path) -> Option<PathBuf> {
+    let is_remote = src.starts_with("//")
+        || src
+            .split_once(':')
+            .is_some_and(|(scheme, _)| !scheme.contains('/'));
+    if src.is_empty() || is_remote || src.starts_with('#') {
+        return None;
+    }
+
+    // Drop any query or fragment, then undo the percent-encoding comrak applies
+    let path = src.split(['?', '#']).next().unwrap_or(src);
+    let decoded = percent_decode_str(&path.replace("&amp;", "&"))
+        .decode_utf8()
+        .ok()?
+        .into_owned();
+    Some(base_dir.join(decoded))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Directory holding the golden-file fixtures
+    fn golden_dir() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden/export")
+    }
+
+    /// Compares an export with its golden file, or rewrites the file if
+    /// `MDVIEW_UPDATE_GOLDEN` is set
+    fn assert_golden(name: &str, actual: &str) {
+        let path = golden_dir().join(name);
+        if std::env::var_os("MDVIEW_UPDATE_GOLDEN").is_some() {
+            fs::write(&path, actual).unwrap();
+            return;
+        }
+
+        let expected = fs::read_to_string(&path).unwrap();
+        if let Some((line, (want, got))) = expected
+            .lines()
+            .zip(actual.lines())
+            .enumerate()
+            .find(|(_, (want, got))| want != got)
+        {
+            panic!(
+                "{} differs at line {}:\n  expected: {}\n  actual:   {}\n\
+                 Rerun with MDVIEW_UPDATE_GOLDEN=1 if the change is intended",
+                name,
+                line + 1,
+                want,
+                got
+            );
+        }
+        assert_eq!(
+            expected.lines().count(),
+            actual.lines().count(),
+            "{} has a different number of lines",
+            name
+        );
+    }
+
+    #[test]
+    fn test_golden_export() {
+        let export =
+            export_document(&golden_dir().join("document.md"), &ExportOptions::default()).unwrap();
+        assert!(export.warnings.is_empty(), "{:?}", export.warnings);
+        assert_golden("document.html", &export.html);
+    }
+
+    #[test]
+    fn test_golden_export_without_inline_images() {
+        let options = ExportOptions {
+            inline_images: false,
+        };
+        let export = export_document(&golden_dir().join("document.md"), &options).unwrap();
+        assert_golden("document-linked-images.html", &export.html);
+    }
+
+    #[test]
+    fn test_export_is_self_contained() {
+        let export =
+            export_document(&golden_dir().join("document.md"), &ExportOptions::default()).unwrap();
+        assert!(!export.html.contains("EventSource"));
+        assert!(!export.html.contains("/__mdview/"));
+        assert!(export.html.contains("<img src=\"data:image/png;base64,"));
+        assert!(export.html.contains("<title>Export fixture</title>"));
+        // The fixture has no math, so KaTeX isn't embedded
+        assert!(!export.html.contains("katex.min.js"));
+        assert!(!export.html.contains("<script>!function"));
+    }
+
+    #[test]
+    fn test_math_embeds_katex() {
+        let html = build_standalone_page("<span data-math-style=\"inline\">x</span>", "Math");
+        assert!(html.contains("<script>!function"));
+        assert!(!html.contains("/__mdview/"));
+    }
+
+    #[test]
+    fn test_inline_images() {
+        let dir = std::env::temp_dir().join(format!("mdview-export-{}", std::process::id()));
+        fs::create_dir_all(dir.join("img")).unwrap();
+        fs::write(dir.join("img/a b.gif"), b"GIF89a").unwrap();
+
+        let mut warnings = Vec::new();
+        let html = inline_images(
+            "<img src=\"img/a%20b.gif\" alt=\"a\" /><img src='https://example.com/x.png'><img src=\"missing.png\">",
+            &dir,
+            &mut warnings,
+        );
+        assert!(html.contains("<img src=\"data:image/gif;base64,R0lGODlh\" alt=\"a\" />"));
+        assert!(html.contains("<img src='https://example.com/x.png'>"));
+        assert!(html.contains("<img src=\"missing.png\">"));
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("missing.png"));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,287 @@
+/// Module for detecting and rendering YAML and TOML front matter
+use crate::template::escape_html;
+use yaml_rust2::{Yaml, YamlLoader};
+
+/// How a front matter block was written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrontMatterFormat {
+    /// Fenced by `---` lines
+    Yaml,
+    /// Fenced by `+++` lines
+    Toml,
+}
+
+/// A front matter value, simplified for display
+#[derive(Debug, Clone, PartialEq)]
+pub enum MetaValue {
+    Text(String),
+    List(Vec<MetaValue>),
+    Table(Vec<(String, MetaValue)>),
+}
+
+/// Metadata parsed from the start of a document
+#[derive(Debug, Clone, PartialEq)]
+pub struct FrontMatter {
+    pub format: FrontMatterFormat,
+    /// Top-level keys in the order they were written
+    pub fields: Vec<(String, MetaValue)>,
+}
+
+impl FrontMatter {
+    /// Returns the `title` key, if it is a plain value
+    pub fn title(&self) -> Option<&str> {
+        self.fields.iter().find_map(|(key, value)| match value {
+            MetaValue::Text(title) if key == "title" && !title.trim().is_empty() => {
+                Some(title.as_str())
+            }
+            _ => None,
+        })
+    }
+
+    /// Renders the metadata as a collapsible table
+    ///
+    /// # Returns
+    ///
+    /// A `<details>` element, or an empty string if there are no fields
+    pub fn to_html(&self) -> String {
+        if self.fields.is_empty() {
+            return String::new();
+        }
+        format!(
+            "<details class=\"front-matter\" open>\n<summary>Metadata</summary>\n{}</details>\n",
+            table_html(&self.fields)
+        )
+    }
+}
+
+/// Splits leading front matter from a document
+///
+/// The front matter block is replaced with blank lines in the returned body, so line
+/// numbers in the body still match the source file. Blocks that don't parse as a
+/// key/value mapping are left in place, since `---` may just be a horizontal rule.
+///
+/// # Arguments
+///
+/// * `content` - The raw markdown content
+///
+/// # Returns
+///
+/// The parsed front matter, if any, and the markdown body to render
+pub fn extract_front_matter(content: &str) -> (Option<FrontMatter>, String) {
+    let Some((format, source, block_len)) = find_block(content) else {
+        return (None, content.to_string());
+    };
+
+    let fields = match format {
+        FrontMatterFormat::Yaml => parse_yaml(source),
+        FrontMatterFormat::Toml => parse_toml(source),
+    };
+
+    match fields {
+        Some(fields) => {
+            let block = &content[..block_len];
+            let mut body = "\n".repeat(block.matches('\n').count());
+            body.push_str(&content[block_len..]);
+            (Some(FrontMatter { format, fields }), body)
+        }
+        None => (None, content.to_string()),
+    }
+}
+
+/// Finds a front matter block at the very start of the document
+///
+/// # Returns
+///
+/// The block's format, the text between its fences, and its length in bytes including
+/// the closing fence
+fn find_block(content: &str) -> Option<(FrontMatterFormat, &str, usize)> {
+    let content_start = content.strip_prefix('\u{feff}').map_or(0, |_| 3);
+    let mut lines = content[content_start..].split_inclusive('\n');
+
+    let opening = lines.next()?;
+    let (format, closers): (_, &[&str]) = match opening.trim_end() {
+        "---" => (FrontMatterFormat::Yaml, &["---", "..."]),
+        "+++" => (FrontMatterFormat::Toml, &["+++"]),
+        _ => return None,
+    };
+
+    let source_start = content_start + opening.len();
+    let mut offset = source_start;
+    for line in lines {
+        if closers.contains(&line.trim_end()) {
+            let source = content.get(source_start..offset)?;
+            return Some((format, source, offset + line.len()));
+        }
+        offset += line.len();
+    }
+    None
+}
+
+/// Parses YAML front matter, which must be a mapping (or empty)
+fn parse_yaml(source: &str) -> Option<Vec<(String, MetaValue)>> {
+    let docs = YamlLoader::load_from_str(source).ok()?;
+    match docs.into_iter().next() {
+        None | Some(Yaml::Null) => Some(Vec::new()),
+        Some(Yaml::Hash(hash)) => Some(
+            hash.into_iter()
+                .map(|(key, value)| (yaml_key(key), yaml_value(value)))
+                .collect(),
+        ),
+        Some(_) => None,
+    }
+}
+
+fn yaml_key(key: Yaml) -> String {
+    match yaml_value(key) {
+        MetaValue::Text(text) => text,
+        _ => "?".to_string(),
+    }
+}
+
+fn yaml_value(value: Yaml) -> MetaValue {
+    match value {
+        Yaml::String(s) | Yaml::Real(s) => MetaValue::Text(s),
+        Yaml::Integer(i) => MetaValue::Text(i.to_string()),
+        Yaml::Boolean(b) => MetaValue::Text(b.to_string()),
+        Yaml::Null | Yaml::BadValue | Yaml::Alias(_) => MetaValue::Text(String::new()),
+        Yaml::Array(items) => MetaValue::List(items.into_iter().map(yaml_value).collect()),
+        Yaml::Hash(hash) => MetaValue::Table(
+            hash.into_iter()
+                .map(|(key, value)| (yaml_key(key), yaml_value(value)))
+                .collect(),
+        ),
+    }
+}
+
+/// Parses TOML front matter
+fn parse_toml(source: &str) -> Option<Vec<(String, MetaValue)>> {
+    let table: toml::Table = source.parse().ok()?;
+    Some(
+        table
+            .into_iter()
+            .map(|(key, value)| (key, toml_value(value)))
+            .collect(),
+    )
+}
+
+fn toml_value(value: toml::Value) -> MetaValue {
+    match value {
+        toml::Value::String(s) => MetaValue::Text(s),
+        toml::Value::Array(items) => MetaValue::List(items.into_iter().map(toml_value).collect()),
+        toml::Value::Table(table) => MetaValue::Table(
+            table
+                .into_iter()
+                .map(|(key, value)| (key, toml_value(value)))
+                .collect(),
+        ),
+        other => MetaValue::Text(other.to_string()),
+    }
+}
+
+/// Renders key/value pairs as a two-column table
+fn table_html(fields: &[(String, MetaValue)]) -> String {
+    let mut html = String::from("<table>\n<tbody>\n");
+    for (key, value) in fields {
+        html.push_str(&format!(
+            "<tr>\n<th>{}</th>\n<td>{}</td>\n</tr>\n",
+            escape_html(key),
+            value_html(value)
+        ));
+    }
+    html.push_str("</tbody>\n</table>\n");
+    html
+}
+
+fn value_html(value: &MetaValue) -> String {
+    match value {
+        MetaValue::Text(text) => escape_html(text),
+        MetaValue::List(items) => {
+            let items: String = items
+                .iter()
+                .map(|item| format!("<li>{}</li>", value_html(item)))
+                .collect();
+            format!("<ul>{}</ul>", items)
+        }
+        MetaValue::Table(fields) => table_html(fields),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_yaml_front_matter() {
+        let md = "---\ntitle: Release notes\ntags:\n  - docs\n  - v2\n---\n# Heading\n";
+        let (front_matter, body) = extract_front_matter(md);
+        let front_matter = front_matter.unwrap();
+
+        assert_eq!(front_matter.format, FrontMatterFormat::Yaml);
+        assert_eq!(front_matter.title(), Some("Release notes"));
+        assert_eq!(
+            front_matter.fields[1],
+            (
+                "tags".to_string(),
+                MetaValue::List(vec![
+                    MetaValue::Text("docs".to_string()),
+                    MetaValue::Text("v2".to_string())
+                ])
+            )
+        );
+        // Lines are blanked rather than removed so line numbers still match
+        assert_eq!(body, "\n\n\n\n\n\n# Heading\n");
+    }
+
+    #[test]
+    fn test_toml_front_matter() {
+        let md =
+            "+++\ntitle = \"Hugo page\"\ndraft = true\n\n[params]\nauthor = \"Sam\"\n+++\nBody\n";
+        let (front_matter, body) = extract_front_matter(md);
+        let front_matter = front_matter.unwrap();
+
+        assert_eq!(front_matter.format, FrontMatterFormat::Toml);
+        assert_eq!(front_matter.title(), Some("Hugo page"));
+        let keys: Vec<&str> = front_matter
+            .fields
+            .iter()
+            .map(|(k, _)| k.as_str())
+            .collect();
+        assert_eq!(keys, ["title", "draft", "params"]);
+        assert!(body.ends_with("\nBody\n"));
+    }
+
+    #[test]
+    fn test_horizontal_rule_is_not_front_matter() {
+        // A rule followed by a setext heading isn't a mapping
+        let md = "---\nJust a heading\n---\n";
+        let (front_matter, body) = extract_front_matter(md);
+        assert!(front_matter.is_none());
+        assert_eq!(body, md);
+
+        let md = "Intro\n\n---\ntitle: x\n---\n";
+        assert!(extract_front_matter(md).0.is_none());
+    }
+
+    #[test]
+    fn test_windows_line_endings() {
+        let (front_matter, body) = extract_front_matter("---\r\ntitle: CRLF\r\n---\r\nBody\r\n");
+        assert_eq!(front_matter.unwrap().title(), Some("CRLF"));
+        assert_eq!(body, "\n\n\nBody\r\n");
+    }
+
+    #[test]
+    fn test_unclosed_block_is_left_alone() {
+        let md = "---\ntitle: Draft\n\nNo closing fence\n";
+        let (front_matter, body) = extract_front_matter(md);
+        assert!(front_matter.is_none());
+        assert_eq!(body, md);
+    }
+
+    #[test]
+    fn test_metadata_table_is_escaped() {
+        let (front_matter, _) = extract_front_matter("---\nauthor: <script>\n---\n");
+        let html = front_matter.unwrap().to_html();
+        assert!(html.starts_with("<details class=\"front-matter\" open>"));
+        assert!(html.contains("<th>author</th>\n<td>&lt;script&gt;</td>"));
+    }
+}
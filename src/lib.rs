@@ -0,0 +1,23 @@
+//! mdview: a markdown viewer with live reload and GitHub styling
+//!
+//! The `mdview` binary is a thin CLI over these modules; they are exposed as a
+//! library so benchmarks can drive the rendering pipeline directly.
+
+pub mod assets;
+pub mod config;
+pub mod daemon;
+pub mod diagrams;
+pub mod directory;
+pub mod editor;
+pub mod export;
+pub mod frontmatter;
+pub mod markdown;
+pub mod server;
+pub mod site;
+pub mod state;
+pub mod stdin;
+pub mod supervisor;
+pub mod template;
+pub mod theme;
+pub mod toc;
+pub mod watcher;
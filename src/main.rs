@@ -1,28 +1,28 @@
-mod daemon;
-mod markdown;
-mod server;
-mod state;
-mod template;
-mod watcher;
-
-use chrono::Utc;
 use clap::{Parser, Subcommand};
 use crossbeam_channel::unbounded;
-use daemon::{daemonize, get_pid, DaemonizeResult};
-use markdown::convert_markdown;
-use nix::sys::signal::{kill, Signal};
-use nix::unistd::Pid;
-use server::{run_server, MarkdownServer};
+use markdown_viewer::config::{BrowserConfig, Config, MAX_DEBOUNCE_MS};
+use markdown_viewer::directory::DirectoryServer;
+use markdown_viewer::export::{export_document, ExportOptions};
+use markdown_viewer::markdown::{render_preview, set_extensions, Extensions};
+use markdown_viewer::server::{
+    base_url, find_available_port, page_title, run_server, MarkdownServer, STDIN_TITLE,
+};
+use markdown_viewer::site::build_site;
+use markdown_viewer::state::get_log_path;
+use markdown_viewer::stdin::{read_document, read_documents};
+use markdown_viewer::supervisor::{
+    ensure_running, send_request, ControlRequest, ControlResponse, SupervisorError,
+};
+use markdown_viewer::template::{build_preview_page, set_custom_css};
+use markdown_viewer::theme::configure_themes;
+use markdown_viewer::toc::update_toc;
+use markdown_viewer::watcher::watch_file;
 use signal_hook::consts::{SIGINT, SIGTERM};
 use signal_hook::flag;
-use state::{get_log_path, Instance, StateFile};
 use std::fs;
-use std::net::TcpListener;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use template::build_html_page;
-use watcher::watch_file;
 
 #[derive(Parser, Debug)]
 #[command(
@@ -35,47 +35,163 @@ struct Cli {
     #[command(subcommand)]
     command: Option<Commands>,
 
-    /// Path to markdown file (runs in foreground, for backwards compatibility)
-    #[arg(value_name = "FILE")]
+    /// Path to a markdown file or a directory of them, or - for stdin (runs in foreground)
+    #[arg(value_name = "PATH")]
     file: Option<PathBuf>,
+
+    /// With -, keep reading stdin and re-render each document ended by a NUL or form feed
+    #[arg(long, requires = "file")]
+    follow: bool,
+
+    /// Code highlighting theme: a bundled theme name or a path to a .tmTheme file
+    #[arg(long, global = true, value_name = "THEME")]
+    theme: Option<String>,
+
+    /// Code highlighting theme for dark mode (defaults to --theme, or gh-dark)
+    #[arg(long, global = true, value_name = "THEME")]
+    dark_theme: Option<String>,
+
+    /// Extra stylesheet added to every page
+    #[arg(long, global = true, value_name = "FILE")]
+    css: Option<PathBuf>,
+
+    /// First port to try when starting a server
+    #[arg(long, global = true)]
+    port: Option<u16>,
+
+    /// Address to listen on
+    #[arg(long, global = true, value_name = "ADDR")]
+    bind: Option<String>,
+
+    /// Command used to open the browser; {url} is replaced with the page URL
+    #[arg(long, global = true, value_name = "COMMAND")]
+    browser: Option<String>,
+
+    /// Command that opens a file at a line, e.g. "code -g {file}:{line}"
+    #[arg(long, global = true, value_name = "COMMAND")]
+    editor: Option<String>,
+
+    /// Milliseconds a file must stay unchanged after a save before the page reloads
+    #[arg(
+        long,
+        global = true,
+        value_name = "MS",
+        value_parser = clap::value_parser!(u64).range(1..=MAX_DEBOUNCE_MS)
+    )]
+    debounce: Option<u64>,
+
+    /// Open the page in a browser
+    #[arg(long, global = true, overrides_with = "no_open")]
+    open: bool,
+
+    /// Don't open browser automatically
+    #[arg(long, global = true, overrides_with = "open")]
+    no_open: bool,
+
+    /// Markdown extensions to turn on, e.g. --enable superscript,wikilinks
+    #[arg(
+        long,
+        global = true,
+        value_name = "EXTENSION",
+        value_delimiter = ',',
+        value_parser = clap::builder::PossibleValuesParser::new(Extensions::NAMES)
+    )]
+    enable: Vec<String>,
+
+    /// Markdown extensions to turn off, e.g. --disable math
+    #[arg(
+        long,
+        global = true,
+        value_name = "EXTENSION",
+        value_delimiter = ',',
+        value_parser = clap::builder::PossibleValuesParser::new(Extensions::NAMES)
+    )]
+    disable: Vec<String>,
 }
 
 #[derive(Subcommand, Debug)]
 enum Commands {
-    /// Start viewer in background daemon mode
+    /// Serve a file from the background supervisor (started on demand)
     Serve {
         /// Path to the markdown file to view
         #[arg(value_name = "FILE")]
         file: PathBuf,
-
-        /// Don't open browser automatically
-        #[arg(long)]
-        no_open: bool,
     },
 
-    /// Stop a running background instance
+    /// Stop serving a file in the background
     Stop {
         /// Path to the markdown file
         #[arg(value_name = "FILE")]
         file: PathBuf,
     },
 
-    /// List all running instances
+    /// List all files served in the background
     List {
         /// Output in JSON format
         #[arg(long)]
         json: bool,
     },
+
+    /// Export a file as a single self-contained HTML page
+    Export {
+        /// Path to the markdown file to export
+        #[arg(value_name = "FILE")]
+        file: PathBuf,
+
+        /// Where to write the page (defaults to FILE with an .html extension)
+        #[arg(short, long, value_name = "PATH")]
+        output: Option<PathBuf>,
+
+        /// Write the page to stdout instead of a file
+        #[arg(long, conflicts_with = "output")]
+        stdout: bool,
+
+        /// Link to local images instead of embedding them as data URIs
+        #[arg(long)]
+        no_inline_images: bool,
+    },
+
+    /// Render a directory of markdown files to a static HTML site
+    Build {
+        /// Directory of markdown files
+        #[arg(value_name = "DIR")]
+        dir: PathBuf,
+
+        /// Directory to write the site to
+        #[arg(short, long, value_name = "PATH", default_value = "site")]
+        output: PathBuf,
+    },
+
+    /// Update the table of contents between <!-- toc --> and <!-- tocstop --> in a file
+    Toc {
+        /// Path to the markdown file to update
+        #[arg(value_name = "FILE")]
+        file: PathBuf,
+
+        /// Don't write anything; exit with an error if the table is out of date
+        #[arg(long)]
+        check: bool,
+
+        /// Deepest heading level to list
+        #[arg(long, value_name = "N", default_value_t = 6, value_parser = clap::value_parser!(u8).range(1..=6))]
+        max_depth: u8,
+    },
+
+    /// Inspect the configuration
+    Config {
+        #[command(subcommand)]
+        action: ConfigCommand,
+    },
 }
 
-/// Finds an available port starting from the specified port
-fn find_available_port(start_port: u16, max_attempts: u16) -> Option<u16> {
-    (start_port..start_port + max_attempts)
-        .find(|port| TcpListener::bind(("127.0.0.1", *port)).is_ok())
+#[derive(Subcommand, Debug)]
+enum ConfigCommand {
+    /// Print the effective configuration, after merging config files and flags
+    Show,
 }
 
 /// Validate that a file exists and is readable
-fn validate_file(file: &PathBuf) -> Result<PathBuf, String> {
+fn validate_file(file: &Path) -> Result<PathBuf, String> {
     if !file.exists() {
         return Err(format!("File '{}' not found", file.display()));
     }
@@ -87,7 +203,7 @@ fn validate_file(file: &PathBuf) -> Result<PathBuf, String> {
 }
 
 /// Run the viewer in foreground mode (original behavior)
-fn run_foreground(file: &PathBuf) {
+fn run_foreground(file: &Path, config: &Config) {
     let file_path = match validate_file(file) {
         Ok(p) => p,
         Err(e) => {
@@ -96,7 +212,11 @@ fn run_foreground(file: &PathBuf) {
         }
     };
 
-    let port = match find_available_port(6914, 100) {
+    let port = match find_available_port(
+        &config.server.bind,
+        config.server.port_start,
+        config.server.port_attempts(),
+    ) {
         Some(p) => p,
         None => {
             eprintln!("Error: Could not find an available port");
@@ -112,12 +232,9 @@ fn run_foreground(file: &PathBuf) {
         }
     };
 
-    let html_content = convert_markdown(&content);
-    let filename = file_path
-        .file_name()
-        .and_then(|n| n.to_str())
-        .unwrap_or("Markdown");
-    let initial_html = build_html_page(&html_content, filename);
+    let rendered = render_preview(&content);
+    let title = page_title(&rendered, &file_path);
+    let initial_html = build_preview_page(&rendered, &title);
 
     let (reload_tx, reload_rx) = unbounded();
 
@@ -137,40 +254,26 @@ fn run_foreground(file: &PathBuf) {
         base_dir,
         file_path_arc,
     ));
+    server.set_editor(config.editor.clone());
 
-    println!("Serving '{}' at http://localhost:{}", filename, port);
+    let url = base_url(&config.server.bind, port);
+    println!("Serving '{}' at {}", file_path.display(), url);
 
-    let url = format!("http://localhost:{}", port);
-    if let Err(e) = open::that(&url) {
-        eprintln!("Warning: Could not open browser: {}", e);
-        eprintln!("Please open {} manually", url);
+    if config.browser.open {
+        open_browser(&url, &config.browser);
     }
 
     let watcher_file_path = file_path.clone();
+    let debounce = config.watch.debounce();
     let watcher_handle = std::thread::spawn(move || {
-        if let Err(e) = watch_file(watcher_file_path, reload_tx) {
+        if let Err(e) = watch_file(watcher_file_path, reload_tx, debounce) {
             eprintln!("File watcher error: {}", e);
         }
     });
 
-    // Set up signal handlers using signal-hook
-    let shutdown = Arc::new(AtomicBool::new(false));
-    flag::register(SIGINT, Arc::clone(&shutdown)).expect("Failed to register SIGINT handler");
-    flag::register(SIGTERM, Arc::clone(&shutdown)).expect("Failed to register SIGTERM handler");
-
-    println!("Press Ctrl+C to stop the server");
-
-    // Check for shutdown signal periodically in a separate thread
-    let shutdown_clone = Arc::clone(&shutdown);
-    std::thread::spawn(move || {
-        while !shutdown_clone.load(Ordering::Relaxed) {
-            std::thread::sleep(std::time::Duration::from_millis(100));
-        }
-        println!("\nShutting down server...");
-        std::process::exit(0);
-    });
+    exit_on_signal();
 
-    if let Err(e) = run_server(port, server) {
+    if let Err(e) = run_server(&config.server.bind, port, server) {
         eprintln!("Server error: {}", e);
         std::process::exit(1);
     }
@@ -178,58 +281,21 @@ fn run_foreground(file: &PathBuf) {
     let _ = watcher_handle.join();
 }
 
-/// Run the viewer as a background daemon
-fn run_serve(file: &PathBuf, no_open: bool) {
-    let file_path = match validate_file(file) {
-        Ok(p) => p,
-        Err(e) => {
-            eprintln!("Error: {}", e);
-            std::process::exit(1);
-        }
-    };
-
-    // Load state and check for existing instance
-    let mut state = match StateFile::load() {
-        Ok(s) => s,
-        Err(e) => {
-            eprintln!("Error loading state: {}", e);
-            std::process::exit(1);
-        }
-    };
-
-    // Clean up stale instances
-    let stale = state.cleanup_stale();
-    for inst in &stale {
-        eprintln!(
-            "Cleaned up stale instance for '{}'",
-            inst.file_path.display()
-        );
-    }
-
-    // Check if already running
-    if let Some(existing) = state.get_instance(&file_path) {
-        if StateFile::is_process_running(existing.pid) {
-            println!(
-                "Already serving '{}' at http://localhost:{}",
-                file_path.display(),
-                existing.port
-            );
-            println!("PID: {}", existing.pid);
-            return;
-        }
-        // Remove stale entry
-        state.remove_instance(&file_path);
-    }
-
-    // Save state after cleanup
-    if !stale.is_empty() {
-        if let Err(e) = state.save() {
-            eprintln!("Warning: Could not save state: {}", e);
-        }
-    }
+/// Whether a path argument means "read from stdin"
+fn is_stdin(path: &Path) -> bool {
+    path == Path::new("-")
+}
 
-    // Find available port
-    let port = match find_available_port(6914, 100) {
+/// Serve markdown read from stdin in the foreground
+///
+/// Without `follow`, stdin is read to the end and rendered once. With it, the page is
+/// served straight away and re-rendered for every document that arrives.
+fn run_stdin(config: &Config, follow: bool) {
+    let port = match find_available_port(
+        &config.server.bind,
+        config.server.port_start,
+        config.server.port_attempts(),
+    ) {
         Some(p) => p,
         None => {
             eprintln!("Error: Could not find an available port");
@@ -237,158 +303,204 @@ fn run_serve(file: &PathBuf, no_open: bool) {
         }
     };
 
-    // Get log file path
-    let log_path = match get_log_path(&file_path, port) {
-        Ok(p) => p,
-        Err(e) => {
-            eprintln!("Error determining log path: {}", e);
-            std::process::exit(1);
+    let content = if follow {
+        String::from("*Waiting for input on stdin...*\n")
+    } else {
+        match read_document(std::io::stdin().lock()) {
+            Ok(c) => c,
+            Err(e) => {
+                eprintln!("Error reading stdin: {}", e);
+                std::process::exit(1);
+            }
         }
     };
 
-    // Print info before forking
-    let filename = file_path
-        .file_name()
-        .and_then(|n| n.to_str())
-        .unwrap_or("Markdown");
-    let url = format!("http://localhost:{}", port);
-
-    println!("Starting mdview daemon for '{}'", filename);
-    println!("URL: {}", url);
-    println!("Log: {}", log_path.display());
-
-    // Daemonize
-    match daemonize(&log_path) {
-        Ok(DaemonizeResult::Parent) => {
-            // Parent process - open browser and exit
-            if !no_open {
-                std::thread::sleep(std::time::Duration::from_millis(200));
-                if let Err(e) = open::that(&url) {
-                    eprintln!("Warning: Could not open browser: {}", e);
-                }
+    let rendered = render_preview(&content);
+    let title = page_title(&rendered, Path::new(STDIN_TITLE));
+    let server = Arc::new(MarkdownServer::for_stdin(build_preview_page(
+        &rendered, &title,
+    )));
+
+    if follow {
+        let reader_server = Arc::clone(&server);
+        std::thread::spawn(move || {
+            let result = read_documents(std::io::stdin().lock(), |document| {
+                reader_server.publish_content(&document)
+            });
+            match result {
+                Ok(()) => println!("End of input on stdin; still serving the last document"),
+                Err(e) => eprintln!("Error reading stdin: {}", e),
             }
-            return;
-        }
-        Ok(DaemonizeResult::Daemon) => {
-            // Daemon process - continue running
-        }
-        Err(e) => {
-            eprintln!("Error daemonizing: {}", e);
-            std::process::exit(1);
-        }
+        });
     }
 
-    // === From here on, we're in the daemon process ===
+    let url = base_url(&config.server.bind, port);
+    println!("Serving stdin at {}", url);
 
-    // Register in state file
-    let instance = Instance {
-        pid: get_pid(),
-        port,
-        file_path: file_path.clone(),
-        started_at: Utc::now(),
-        log_file: log_path,
-    };
-
-    let mut state = StateFile::load().unwrap_or_default();
-    state.add_instance(instance);
-    if let Err(e) = state.save() {
-        eprintln!("Warning: Could not save state: {}", e);
+    if config.browser.open {
+        open_browser(&url, &config.browser);
     }
 
-    // Log startup
-    println!(
-        "[{}] mdview daemon started for '{}'",
-        Utc::now().format("%Y-%m-%d %H:%M:%S"),
-        file_path.display()
-    );
-    println!("PID: {}", get_pid());
-    println!("Port: {}", port);
+    exit_on_signal();
 
-    // Set up the server
-    let content = match fs::read_to_string(&file_path) {
-        Ok(c) => c,
+    if let Err(e) = run_server(&config.server.bind, port, server) {
+        eprintln!("Server error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+/// Serve a directory of markdown files in the foreground
+fn run_directory(dir: &Path, config: &Config) {
+    let root = match dir.canonicalize() {
+        Ok(p) => p,
         Err(e) => {
-            eprintln!("Error reading file: {}", e);
-            cleanup_on_shutdown(&file_path);
+            eprintln!("Error resolving directory path: {}", e);
             std::process::exit(1);
         }
     };
 
-    let html_content = convert_markdown(&content);
-    let initial_html = build_html_page(&html_content, filename);
-
-    let (reload_tx, reload_rx) = unbounded();
-
-    let file_path_arc: Arc<std::path::Path> = Arc::from(file_path.as_path());
-
-    let base_dir = match file_path.parent() {
-        Some(dir) => Arc::from(dir),
+    let port = match find_available_port(
+        &config.server.bind,
+        config.server.port_start,
+        config.server.port_attempts(),
+    ) {
+        Some(p) => p,
         None => {
-            eprintln!("Error: Could not determine parent directory");
-            cleanup_on_shutdown(&file_path);
+            eprintln!("Error: Could not find an available port");
             std::process::exit(1);
         }
     };
 
-    let server = Arc::new(MarkdownServer::new(
-        initial_html,
-        reload_rx,
-        base_dir,
-        file_path_arc.clone(),
+    let server = Arc::new(DirectoryServer::new(
+        root.clone(),
+        config.editor.clone(),
+        config.watch.debounce(),
     ));
 
-    // Start file watcher
-    let watcher_file_path = file_path.clone();
-    std::thread::spawn(move || {
-        if let Err(e) = watch_file(watcher_file_path, reload_tx) {
-            eprintln!("File watcher error: {}", e);
-        }
-    });
+    let url = base_url(&config.server.bind, port);
+    println!("Serving '{}' at {}", root.display(), url);
+
+    if config.browser.open {
+        open_browser(&url, &config.browser);
+    }
 
-    // Set up signal handlers for graceful shutdown
+    exit_on_signal();
+
+    if let Err(e) = server.run(&config.server.bind, port) {
+        eprintln!("Server error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+/// Open a URL with the configured browser command, or the system default browser
+fn open_browser(url: &str, browser: &BrowserConfig) {
+    let result = match browser.command_for(url) {
+        Ok(Some(args)) => std::process::Command::new(&args[0])
+            .args(&args[1..])
+            .spawn()
+            .map(|_| ())
+            .map_err(|e| e.to_string()),
+        Ok(None) => open::that(url).map_err(|e| e.to_string()),
+        Err(message) => Err(message),
+    };
+
+    if let Err(e) = result {
+        eprintln!("Warning: Could not open browser: {}", e);
+        eprintln!("Please open {} manually", url);
+    }
+}
+
+/// Exit cleanly on SIGINT/SIGTERM while a foreground server is running
+fn exit_on_signal() {
+    // Set up signal handlers using signal-hook
     let shutdown = Arc::new(AtomicBool::new(false));
     flag::register(SIGINT, Arc::clone(&shutdown)).expect("Failed to register SIGINT handler");
     flag::register(SIGTERM, Arc::clone(&shutdown)).expect("Failed to register SIGTERM handler");
 
-    // Shutdown monitor thread
-    let shutdown_clone = Arc::clone(&shutdown);
-    let cleanup_path = file_path.clone();
+    println!("Press Ctrl+C to stop the server");
+
+    // Check for shutdown signal periodically in a separate thread
     std::thread::spawn(move || {
-        while !shutdown_clone.load(Ordering::Relaxed) {
+        while !shutdown.load(Ordering::Relaxed) {
             std::thread::sleep(std::time::Duration::from_millis(100));
         }
-        println!(
-            "[{}] Received shutdown signal",
-            Utc::now().format("%Y-%m-%d %H:%M:%S")
-        );
-        cleanup_on_shutdown(&cleanup_path);
+        println!("\nShutting down server...");
         std::process::exit(0);
     });
+}
 
-    println!(
-        "[{}] Server running on http://localhost:{}",
-        Utc::now().format("%Y-%m-%d %H:%M:%S"),
-        port
-    );
-
-    // Run the server
-    if let Err(e) = run_server(port, server) {
-        eprintln!("Server error: {}", e);
-        cleanup_on_shutdown(&file_path);
+/// Serve a file from the background supervisor, starting it if needed
+fn run_serve(file: &Path, config: &Config, render_flags: bool) {
+    if is_stdin(file) {
+        eprintln!("Error: stdin can only be served in the foreground; run 'mdview -' instead");
         std::process::exit(1);
     }
-}
+    let file_path = match validate_file(file) {
+        Ok(p) => p,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    };
 
-/// Clean up state file on shutdown
-fn cleanup_on_shutdown(file_path: &std::path::Path) {
-    if let Ok(mut state) = StateFile::load() {
-        state.remove_instance(file_path);
-        let _ = state.save();
+    match ensure_running(&config.server, &config.editor, &config.watch) {
+        Ok(true) => {
+            if let Ok(log_path) = get_log_path() {
+                println!("Started mdview supervisor (log: {})", log_path.display());
+            }
+        }
+        Ok(false) if render_flags => {
+            eprintln!(
+                "Note: the supervisor is already running, so theme and style options are ignored"
+            );
+            eprintln!("Stop all background documents to restart it with new settings");
+        }
+        Ok(false) => {}
+        Err(e) => {
+            eprintln!("Error starting supervisor: {}", e);
+            std::process::exit(1);
+        }
+    }
+
+    // Sent every time, so the document picks up a changed editor even if the
+    // supervisor was started with another one
+    let request = ControlRequest::Serve {
+        file: file_path.clone(),
+        editor: config.editor.clone(),
+        watch: config.watch.clone(),
+    };
+    match send_request(&request) {
+        Ok(ControlResponse::Serving {
+            url,
+            already_serving,
+        }) => {
+            if already_serving {
+                println!("Already serving '{}' at {}", file_path.display(), url);
+                return;
+            }
+
+            println!("Serving '{}' at {}", file_path.display(), url);
+            if config.browser.open {
+                open_browser(&url, &config.browser);
+            }
+        }
+        Ok(ControlResponse::Error { message }) => {
+            eprintln!("Error: {}", message);
+            std::process::exit(1);
+        }
+        Ok(other) => {
+            eprintln!("Error: Unexpected response from supervisor: {:?}", other);
+            std::process::exit(1);
+        }
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
     }
 }
 
-/// Stop a running background instance
-fn run_stop(file: &PathBuf) {
+/// Stop serving a file in the background supervisor
+fn run_stop(file: &Path) {
     let file_path = match validate_file(file) {
         Ok(p) => p,
         Err(e) => {
@@ -403,70 +515,57 @@ fn run_stop(file: &PathBuf) {
         }
     };
 
-    let mut state = match StateFile::load() {
-        Ok(s) => s,
-        Err(e) => {
-            eprintln!("Error loading state: {}", e);
-            std::process::exit(1);
-        }
+    let request = ControlRequest::Stop {
+        file: file_path.clone(),
     };
-
-    let instance = match state.get_instance(&file_path) {
-        Some(i) => i.clone(),
-        None => {
-            eprintln!("No running instance found for '{}'", file_path.display());
+    match send_request(&request) {
+        Ok(ControlResponse::Stopped { exiting }) => {
+            println!("Stopped serving '{}'", file_path.display());
+            if exiting {
+                println!("No documents left, supervisor exiting");
+            }
+        }
+        Ok(ControlResponse::Error { message }) => {
+            eprintln!("{}", message);
             std::process::exit(1);
         }
-    };
-
-    // Send SIGTERM to the process
-    let pid = Pid::from_raw(instance.pid);
-    match kill(pid, Signal::SIGTERM) {
-        Ok(()) => {
-            println!("Sent stop signal to mdview (PID {})", instance.pid);
+        Ok(other) => {
+            eprintln!("Error: Unexpected response from supervisor: {:?}", other);
+            std::process::exit(1);
         }
-        Err(nix::errno::Errno::ESRCH) => {
-            println!(
-                "Process {} not running (stale entry), cleaning up",
-                instance.pid
-            );
+        Err(SupervisorError::NotRunning) => {
+            eprintln!("No running instance found for '{}'", file_path.display());
+            std::process::exit(1);
         }
         Err(e) => {
-            eprintln!("Failed to stop process {}: {}", instance.pid, e);
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
         }
     }
-
-    // Remove from state
-    state.remove_instance(&file_path);
-    if let Err(e) = state.save() {
-        eprintln!("Warning: Could not save state: {}", e);
-    }
-
-    println!("Stopped serving '{}'", file_path.display());
 }
 
-/// List all running instances
+/// List all documents served by the background supervisor
 fn run_list(json_output: bool) {
-    let mut state = match StateFile::load() {
-        Ok(s) => s,
+    let (pid, port, documents) = match send_request(&ControlRequest::List) {
+        Ok(ControlResponse::Documents {
+            pid,
+            port,
+            documents,
+        }) => (pid, port, documents),
+        Ok(other) => {
+            eprintln!("Error: Unexpected response from supervisor: {:?}", other);
+            std::process::exit(1);
+        }
+        Err(SupervisorError::NotRunning) => (0, 0, Vec::new()),
         Err(e) => {
-            eprintln!("Error loading state: {}", e);
+            eprintln!("Error: {}", e);
             std::process::exit(1);
         }
     };
 
-    // Clean up stale instances
-    let stale = state.cleanup_stale();
-    if !stale.is_empty() {
-        if let Err(e) = state.save() {
-            eprintln!("Warning: Could not save state: {}", e);
-        }
-    }
-
     if json_output {
         // JSON output
-        let instances: Vec<_> = state.all_instances().collect();
-        match serde_json::to_string_pretty(&instances) {
+        match serde_json::to_string_pretty(&documents) {
             Ok(json) => println!("{}", json),
             Err(e) => {
                 eprintln!("Error serializing to JSON: {}", e);
@@ -477,44 +576,249 @@ fn run_list(json_output: bool) {
     }
 
     // Human-readable output
-    let instances: Vec<_> = state.all_instances().collect();
-
-    if instances.is_empty() {
+    if documents.is_empty() {
         println!("No running mdview instances");
         return;
     }
 
-    println!(
-        "{:<6} {:<6} {:<20} {}",
-        "PID", "PORT", "STARTED", "FILE"
-    );
-    println!("{}", "-".repeat(70));
-
-    for inst in instances {
-        let started = inst.started_at.format("%Y-%m-%d %H:%M:%S");
-        let status = if StateFile::is_process_running(inst.pid) {
-            ""
-        } else {
-            " (stale)"
-        };
+    println!("Supervisor PID {} on port {}", pid, port);
+    println!("{:<20} {:<36} FILE", "STARTED", "URL");
+    println!("{}", "-".repeat(90));
+
+    for doc in documents {
+        let started = doc.started_at.format("%Y-%m-%d %H:%M:%S");
         println!(
-            "{:<6} {:<6} {:<20} {}{}",
-            inst.pid,
-            inst.port,
+            "{:<20} {:<36} {}",
             started,
-            inst.file_path.display(),
-            status
+            doc.url,
+            doc.file_path.display()
+        );
+    }
+}
+
+/// Export a file as a self-contained HTML page
+fn run_export(file: &Path, output: Option<&Path>, to_stdout: bool, inline_images: bool) {
+    let file_path = match validate_file(file) {
+        Ok(p) => p,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let export = match export_document(&file_path, &ExportOptions { inline_images }) {
+        Ok(export) => export,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    };
+    for warning in &export.warnings {
+        eprintln!("Warning: {}", warning);
+    }
+
+    if to_stdout {
+        print!("{}", export.html);
+        return;
+    }
+
+    let output = output
+        .map(Path::to_path_buf)
+        .unwrap_or_else(|| file.with_extension("html"));
+    if let Err(e) = fs::write(&output, &export.html) {
+        eprintln!("Error writing '{}': {}", output.display(), e);
+        std::process::exit(1);
+    }
+    eprintln!("Exported '{}' to '{}'", file.display(), output.display());
+}
+
+/// Build a static site, exiting with an error if any internal links are broken
+fn run_build(dir: &Path, output: &Path) {
+    let report = match build_site(dir, output) {
+        Ok(report) => report,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    println!(
+        "Built {} page(s) and copied {} file(s) into '{}'",
+        report.pages,
+        report.files_copied,
+        output.display()
+    );
+    if report.broken_links.is_empty() {
+        return;
+    }
+    eprintln!("{} broken link(s):", report.broken_links.len());
+    for link in &report.broken_links {
+        eprintln!("  {}", link);
+    }
+    std::process::exit(1);
+}
+
+/// Update a file's table of contents in place, or with `check`, report whether it's stale
+fn run_toc(file: &Path, check: bool, max_depth: u8) {
+    let content = match fs::read_to_string(file) {
+        Ok(content) => content,
+        Err(e) => {
+            eprintln!("Error reading '{}': {}", file.display(), e);
+            std::process::exit(1);
+        }
+    };
+    let updated = match update_toc(&content, max_depth) {
+        Ok(updated) => updated,
+        Err(e) => {
+            eprintln!("Error: {}: {}", file.display(), e);
+            std::process::exit(1);
+        }
+    };
+
+    if updated == content {
+        if !check {
+            eprintln!("Table of contents in '{}' is up to date", file.display());
+        }
+        return;
+    }
+    if check {
+        eprintln!(
+            "Table of contents in '{}' is out of date; run without --check to update it",
+            file.display()
         );
+        std::process::exit(1);
+    }
+    if let Err(e) = fs::write(file, &updated) {
+        eprintln!("Error writing '{}': {}", file.display(), e);
+        std::process::exit(1);
+    }
+    eprintln!("Updated the table of contents in '{}'", file.display());
+}
+
+/// Print the effective configuration and the files it was loaded from
+fn run_config_show(config: &Config) {
+    if config.sources.is_empty() {
+        println!("# No config files found, using defaults");
+    }
+    for source in &config.sources {
+        println!("# Loaded from: {}", source.display());
+    }
+
+    match config.to_toml() {
+        Ok(toml) => print!("{}", toml),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Apply command-line flags on top of the settings loaded from config files
+fn apply_cli_overrides(cli: &Cli, config: &mut Config) {
+    if let Some(theme) = &cli.theme {
+        config.theme.light = theme.clone();
+        config.theme.dark = theme.clone();
+    }
+    if let Some(dark_theme) = &cli.dark_theme {
+        config.theme.dark = dark_theme.clone();
+    }
+    if let Some(css) = &cli.css {
+        config.theme.custom_css = Some(css.clone());
+    }
+    if let Some(port) = cli.port {
+        // Keep the configured number of fallback ports, starting from the new one
+        let attempts = config.server.port_attempts();
+        config.server.port_start = port;
+        config.server.port_end = port.saturating_add(attempts.saturating_sub(1));
+    }
+    if let Some(bind) = &cli.bind {
+        config.server.bind = bind.clone();
+    }
+    if let Some(browser) = &cli.browser {
+        config.browser.command = Some(browser.clone());
+    }
+    if let Some(editor) = &cli.editor {
+        config.editor.command = Some(editor.clone());
+    }
+    if let Some(debounce) = cli.debounce {
+        config.watch.debounce_ms = debounce;
+    }
+    if cli.open {
+        config.browser.open = true;
+    }
+    if cli.no_open {
+        config.browser.open = false;
+    }
+
+    // Names are checked by clap, so setting them can't fail
+    for name in &cli.enable {
+        let _ = config.markdown.set(name, true);
+    }
+    for name in &cli.disable {
+        let _ = config.markdown.set(name, false);
+    }
+}
+
+/// Configure themes, markdown extensions and custom styles before anything is rendered
+fn apply_render_settings(config: &Config) {
+    if let Err(e) = configure_themes(&config.theme.light, &config.theme.dark) {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+
+    set_extensions(config.markdown.clone());
+
+    if let Some(path) = &config.theme.custom_css {
+        match fs::read_to_string(path) {
+            Ok(css) => set_custom_css(css),
+            Err(e) => {
+                eprintln!("Error reading stylesheet '{}': {}", path.display(), e);
+                std::process::exit(1);
+            }
+        }
     }
 }
 
 fn main() {
     let cli = Cli::parse();
 
+    let mut config = match Config::load() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    };
+    apply_cli_overrides(&cli, &mut config);
+
+    if let Some(Commands::Config {
+        action: ConfigCommand::Show,
+    }) = &cli.command
+    {
+        run_config_show(&config);
+        return;
+    }
+
+    // Only commands that render can be broken by theme and stylesheet settings
+    match &cli.command {
+        Some(Commands::Serve { .. } | Commands::Export { .. } | Commands::Build { .. }) => {
+            apply_render_settings(&config)
+        }
+        None if cli.file.is_some() => apply_render_settings(&config),
+        // Headings are found with the configured extensions, e.g. skipping front matter
+        Some(Commands::Toc { .. }) => set_extensions(config.markdown.clone()),
+        _ => {}
+    }
+    let render_flags = cli.theme.is_some()
+        || cli.dark_theme.is_some()
+        || cli.css.is_some()
+        || !cli.enable.is_empty()
+        || !cli.disable.is_empty();
+
     match (&cli.command, &cli.file) {
         // Subcommand provided (ignore any trailing file argument)
-        (Some(Commands::Serve { file, no_open }), _) => {
-            run_serve(file, *no_open);
+        (Some(Commands::Serve { file }), _) => {
+            run_serve(file, &config, render_flags);
         }
         (Some(Commands::Stop { file }), _) => {
             run_stop(file);
@@ -522,9 +826,45 @@ fn main() {
         (Some(Commands::List { json }), _) => {
             run_list(*json);
         }
+        (
+            Some(Commands::Export {
+                file,
+                output,
+                stdout,
+                no_inline_images,
+            }),
+            _,
+        ) => {
+            run_export(file, output.as_deref(), *stdout, !no_inline_images);
+        }
+        (Some(Commands::Build { dir, output }), _) => {
+            run_build(dir, output);
+        }
+        (
+            Some(Commands::Toc {
+                file,
+                check,
+                max_depth,
+            }),
+            _,
+        ) => {
+            run_toc(file, *check, *max_depth);
+        }
+        (Some(Commands::Config { .. }), _) => unreachable!("handled above"),
+        (None, Some(path)) if cli.follow && !is_stdin(path) => {
+            eprintln!("Error: --follow only applies when reading from stdin (-)");
+            std::process::exit(1);
+        }
+        (None, Some(path)) if is_stdin(path) => {
+            run_stdin(&config, cli.follow);
+        }
+        // Foreground mode: a directory gets an index of its markdown files
+        (None, Some(path)) if path.is_dir() => {
+            run_directory(path, &config);
+        }
         // Legacy mode: file provided without subcommand
         (None, Some(file)) => {
-            run_foreground(file);
+            run_foreground(file, &config);
         }
         // No arguments - show help
         (None, None) => {
@@ -1,9 +1,252 @@
 /// Module for converting markdown to HTML using comrak
+use crate::config::get_config_dir;
+use crate::diagrams::{render_diagram_html, DiagramTool};
+use crate::frontmatter::{extract_front_matter, FrontMatter};
+use crate::template::escape_html;
+use crate::theme::CLASS_PREFIX;
+use crate::toc::toc_html;
+use comrak::adapters::SyntaxHighlighterAdapter;
+use comrak::html::collect_text;
+use comrak::nodes::{AstNode, NodeValue};
 use comrak::options::Plugins;
-use comrak::plugins::syntect::SyntectAdapterBuilder;
-use comrak::{markdown_to_html_with_plugins, Options};
-use std::io::Cursor;
+use comrak::plugins::syntect::{SyntectAdapter, SyntectAdapterBuilder};
+use comrak::{format_html_with_plugins, parse_document, Anchorizer, Arena, Options};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
 use syntect::highlighting::ThemeSet;
+use syntect::parsing::{SyntaxDefinition, SyntaxSet};
+
+/// Process-wide highlighter, built on first use and shared across renders and threads
+static HIGHLIGHTER: OnceLock<SyntectAdapter> = OnceLock::new();
+
+/// Process-wide extension settings; see `set_extensions`
+static EXTENSIONS: OnceLock<Extensions> = OnceLock::new();
+
+/// A rendered document and the metadata found in it
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenderedDocument {
+    /// HTML for the page body, starting with the front matter table if there is one
+    pub html: String,
+    /// The front matter's `title`, if it has one
+    pub title: Option<String>,
+    /// The document's headings in order
+    pub headings: Vec<Heading>,
+}
+
+/// A heading and the anchor comrak gives it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Heading {
+    /// 1 for `#`, up to 6 for `######`
+    pub level: u8,
+    /// The heading's plain text, without formatting
+    pub text: String,
+    /// The heading's `id` (without `#`), when the `header_ids` extension is on
+    pub anchor: String,
+}
+
+/// Markdown extensions that can be switched on or off
+///
+/// The defaults match GitHub's rendering.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Extensions {
+    pub strikethrough: bool,
+    pub table: bool,
+    pub autolink: bool,
+    pub tasklist: bool,
+    /// Escape raw HTML tags GitHub disallows, such as `<script>` and `<iframe>`
+    pub tagfilter: bool,
+    /// GitHub alerts (`> [!NOTE]`)
+    pub alerts: bool,
+    /// Give headings `id` attributes so they can be linked to
+    pub header_ids: bool,
+    /// Footnote references (`[^1]`) and definitions
+    pub footnotes: bool,
+    /// Show leading `---` YAML or `+++` TOML front matter as a metadata table
+    pub front_matter: bool,
+    /// Definition lists (a term followed by `: definition` lines)
+    pub description_lists: bool,
+    /// Superscript with `^text^`
+    pub superscript: bool,
+    /// `$inline$`, `$$display$$` and ` ```math ` blocks
+    pub math: bool,
+    /// `[[Page]]` and `[[Page|title]]` links
+    pub wikilinks: bool,
+    /// Underline with `__text__` instead of bold
+    pub underline: bool,
+    /// Hidden text with `||spoiler||`
+    pub spoiler: bool,
+    /// Block quotes fenced by `>>>` lines
+    pub multiline_block_quotes: bool,
+}
+
+impl Default for Extensions {
+    fn default() -> Self {
+        Self {
+            strikethrough: true,
+            table: true,
+            autolink: true,
+            tasklist: true,
+            tagfilter: true,
+            alerts: true,
+            header_ids: true,
+            footnotes: true,
+            front_matter: true,
+            description_lists: false,
+            superscript: false,
+            math: true,
+            wikilinks: false,
+            underline: false,
+            spoiler: false,
+            multiline_block_quotes: false,
+        }
+    }
+}
+
+impl Extensions {
+    /// Names accepted by `set`, matching the config keys
+    pub const NAMES: &'static [&'static str] = &[
+        "strikethrough",
+        "table",
+        "autolink",
+        "tasklist",
+        "tagfilter",
+        "alerts",
+        "header_ids",
+        "footnotes",
+        "front_matter",
+        "description_lists",
+        "superscript",
+        "math",
+        "wikilinks",
+        "underline",
+        "spoiler",
+        "multiline_block_quotes",
+    ];
+
+    /// Switches an extension on or off by name
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the name isn't one of `NAMES`
+    pub fn set(&mut self, name: &str, enabled: bool) -> Result<(), String> {
+        let flag = match name {
+            "strikethrough" => &mut self.strikethrough,
+            "table" => &mut self.table,
+            "autolink" => &mut self.autolink,
+            "tasklist" => &mut self.tasklist,
+            "tagfilter" => &mut self.tagfilter,
+            "alerts" => &mut self.alerts,
+            "header_ids" => &mut self.header_ids,
+            "footnotes" => &mut self.footnotes,
+            "front_matter" => &mut self.front_matter,
+            "description_lists" => &mut self.description_lists,
+            "superscript" => &mut self.superscript,
+            "math" => &mut self.math,
+            "wikilinks" => &mut self.wikilinks,
+            "underline" => &mut self.underline,
+            "spoiler" => &mut self.spoiler,
+            "multiline_block_quotes" => &mut self.multiline_block_quotes,
+            _ => {
+                return Err(format!(
+                    "Unknown extension '{}'. Expected one of: {}",
+                    name,
+                    Self::NAMES.join(", ")
+                ))
+            }
+        };
+        *flag = enabled;
+        Ok(())
+    }
+}
+
+/// Sets the extensions used by `convert_markdown` for the rest of the process
+///
+/// Has no effect once a document has been rendered.
+pub fn set_extensions(extensions: Extensions) {
+    let _ = EXTENSIONS.set(extensions);
+}
+
+/// Returns the process-wide extension settings
+pub fn extensions() -> &'static Extensions {
+    EXTENSIONS.get_or_init(Extensions::default)
+}
+
+/// Returns the directory users can drop extra `.sublime-syntax` files into
+///
+/// This is `syntaxes/` inside mdview's config directory (`~/.config/mdview` on Linux).
+pub fn user_syntax_dir() -> Option<PathBuf> {
+    get_config_dir().ok().map(|dir| dir.join("syntaxes"))
+}
+
+/// Loads the bundled syntax definitions plus any user-supplied ones
+///
+/// The bundled set is bat's, which adds TypeScript, TSX, Vue, Svelte, Terraform, Nix,
+/// Zig, Dockerfile, TOML and many more to syntect's defaults. Each `.sublime-syntax`
+/// file in `user_dir` is loaded on its own, so a broken file is reported and skipped
+/// without affecting the others.
+///
+/// # Arguments
+///
+/// * `user_dir` - Directory to load extra syntaxes from, if any
+pub fn load_syntax_set(user_dir: Option<&Path>) -> SyntaxSet {
+    let bundled = two_face::syntax::extra_newlines();
+    let entries = match user_dir.map(fs::read_dir) {
+        Some(Ok(entries)) => entries,
+        _ => return bundled,
+    };
+
+    let mut builder = bundled.into_builder();
+    for path in entries.filter_map(|e| e.ok()).map(|e| e.path()) {
+        if path.extension().and_then(|ext| ext.to_str()) != Some("sublime-syntax") {
+            continue;
+        }
+        let name = path.file_stem().and_then(|s| s.to_str());
+        let loaded = fs::read_to_string(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|source| {
+                SyntaxDefinition::load_from_str(&source, true, name).map_err(|e| e.to_string())
+            });
+        match loaded {
+            Ok(definition) => builder.add(definition),
+            Err(e) => eprintln!("Warning: Skipping syntax file '{}': {}", path.display(), e),
+        }
+    }
+    builder.build()
+}
+
+/// Builds a syntax highlighter from the bundled and user syntax definitions
+///
+/// Tokens are marked up with prefixed CSS classes rather than inline colours, so the
+/// same HTML can be coloured by either the light or the dark theme; see
+/// `theme::syntax_css`. Fences in a language no syntax recognises are rendered as
+/// plain text.
+///
+/// This is expensive (it loads every syntax definition), so rendering goes through a
+/// shared instance instead; see `highlighter`.
+pub fn build_highlighter() -> SyntectAdapter {
+    highlighter_for(load_syntax_set(user_syntax_dir().as_deref()))
+}
+
+/// Builds a syntax highlighter for the given syntax set
+pub fn highlighter_for(syntax_set: SyntaxSet) -> SyntectAdapter {
+    SyntectAdapterBuilder::new()
+        .syntax_set(syntax_set)
+        // Themes aren't used for classed output; skip loading syntect's defaults
+        .theme_set(ThemeSet::new())
+        .css_with_class_prefix(CLASS_PREFIX)
+        .build()
+}
+
+/// Returns the shared syntax highlighter, building it on first use
+pub fn highlighter() -> &'static SyntectAdapter {
+    HIGHLIGHTER.get_or_init(build_highlighter)
+}
 
 /// Converts markdown content to HTML with GitHub-flavored markdown extensions
 ///
@@ -18,20 +261,316 @@ use syntect::highlighting::ThemeSet;
 ///
 /// The rendered HTML as a String
 pub fn convert_markdown(content: &str) -> String {
+    convert_markdown_with_highlighter(content, highlighter())
+}
+
+/// Converts markdown content to HTML using the given syntax highlighter
+///
+/// # Arguments
+///
+/// * `content` - The raw markdown content as a string
+/// * `adapter` - The syntax highlighter to use for fenced code blocks
+///
+/// # Returns
+///
+/// The rendered HTML as a String
+pub fn convert_markdown_with_highlighter(content: &str, adapter: &SyntectAdapter) -> String {
+    convert_markdown_with(content, extensions(), adapter)
+}
+
+/// Converts markdown content to HTML with the given extensions and syntax highlighter
+///
+/// # Arguments
+///
+/// * `content` - The raw markdown content as a string
+/// * `extensions` - Which markdown extensions to enable
+/// * `adapter` - The syntax highlighter to use for fenced code blocks
+///
+/// # Returns
+///
+/// The rendered HTML as a String
+pub fn convert_markdown_with(
+    content: &str,
+    extensions: &Extensions,
+    adapter: &SyntectAdapter,
+) -> String {
+    render_document_with(content, extensions, adapter).html
+}
+
+/// Renders a document, along with the metadata in its front matter
+///
+/// # Arguments
+///
+/// * `content` - The raw markdown content as a string
+///
+/// # Returns
+///
+/// The rendered HTML and the document's title, if its front matter sets one
+pub fn render_document(content: &str) -> RenderedDocument {
+    render_document_with(content, extensions(), highlighter())
+}
+
+/// Renders a document for the live preview
+///
+/// Like `render_document`, but every block element carries a
+/// `data-sourcepos="startline:col-endline:col"` attribute, so the page can find the
+/// block for a given source line (to follow an editor's cursor, for example).
+pub fn render_preview(content: &str) -> RenderedDocument {
+    render(content, extensions(), highlighter(), true)
+}
+
+/// Renders a document with the given extensions and syntax highlighter
+///
+/// When the `front_matter` extension is on, a leading `---` YAML or `+++` TOML block is
+/// removed from the body and shown as a metadata table instead.
+pub fn render_document_with(
+    content: &str,
+    extensions: &Extensions,
+    adapter: &SyntectAdapter,
+) -> RenderedDocument {
+    render(content, extensions, adapter, false)
+}
+
+/// Renders a document, with source positions on its blocks if `sourcepos` is set
+fn render(
+    content: &str,
+    extensions: &Extensions,
+    adapter: &SyntectAdapter,
+    sourcepos: bool,
+) -> RenderedDocument {
+    let mut options = comrak_options(extensions);
+    // Front matter is blanked out rather than removed, so positions match the file
+    options.render.sourcepos = sourcepos;
+    let sourcepos_adapter = SourceposHighlighter(adapter);
+    let mut plugins = Plugins::default();
+    plugins.render.codefence_syntax_highlighter = if sourcepos {
+        Some(&sourcepos_adapter)
+    } else {
+        Some(adapter)
+    };
+
+    let (front_matter, body) = if extensions.front_matter {
+        extract_front_matter(content)
+    } else {
+        (None, content.to_string())
+    };
+
+    let arena = Arena::new();
+    let root = parse_document(&arena, &body, &options);
+    replace_diagram_fences(root, sourcepos);
+    let headings = collect_headings(root);
+    if extensions.header_ids {
+        expand_toc_placeholders(root, &body, &headings, sourcepos);
+    }
+
+    let mut html = front_matter
+        .as_ref()
+        .map(FrontMatter::to_html)
+        .unwrap_or_default();
+    format_html_with_plugins(root, &options, &mut html, &plugins)
+        .expect("Writing HTML to a String can't fail");
+
+    RenderedDocument {
+        html,
+        title: front_matter.and_then(|fm| fm.title().map(String::from)),
+        headings,
+    }
+}
+
+/// Parses a document with the current extensions and passes its syntax tree to `f`
+///
+/// Front matter is handled as in `render_document`, so source positions in the tree
+/// match lines in `content`.
+pub fn with_syntax_tree<T>(content: &str, f: impl for<'a> FnOnce(&'a AstNode<'a>) -> T) -> T {
+    let extensions = extensions();
+    let body = if extensions.front_matter {
+        extract_front_matter(content).1
+    } else {
+        content.to_string()
+    };
+
+    let arena = Arena::new();
+    f(parse_document(&arena, &body, &comrak_options(extensions)))
+}
+
+/// Lists the headings in a parsed document
+///
+/// Anchors are generated the same way comrak generates heading ids, including the
+/// `-1`, `-2` suffixes for repeated headings, so they can be linked to.
+pub fn collect_headings<'a>(root: &'a AstNode<'a>) -> Vec<Heading> {
+    let mut anchorizer = Anchorizer::new();
+    root.descendants()
+        .filter_map(|node| match node.data().value {
+            NodeValue::Heading(ref heading) => Some((heading.level, node)),
+            _ => None,
+        })
+        .map(|(level, node)| {
+            let text = collect_text(node);
+            Heading {
+                level,
+                anchor: anchorizer.anchorize(&text),
+                text,
+            }
+        })
+        .collect()
+}
+
+/// Placeholders that stand for a table of contents when they are a paragraph of their own
+const TOC_PLACEHOLDERS: &[&str] = &["[TOC]", "[[_TOC_]]"];
+
+/// Replaces `[TOC]` and `[[_TOC_]]` paragraphs with a table of contents
+///
+/// Placeholders are matched against the paragraph's source, since `_TOC_` would
+/// otherwise be parsed as emphasis (or as a link, with the `wikilinks` extension).
+fn expand_toc_placeholders<'a>(
+    root: &'a AstNode<'a>,
+    source: &str,
+    headings: &[Heading],
+    sourcepos: bool,
+) {
+    let lines: Vec<&str> = source.lines().collect();
+    let placeholders: Vec<_> = root
+        .descendants()
+        .filter(|node| {
+            let ast = node.data();
+            let (start, end) = (ast.sourcepos.start, ast.sourcepos.end);
+            matches!(ast.value, NodeValue::Paragraph)
+                && start.line == end.line
+                && lines
+                    .get(start.line.wrapping_sub(1))
+                    .and_then(|line| line.get(start.column.saturating_sub(1)..end.column))
+                    .is_some_and(|text| TOC_PLACEHOLDERS.contains(&text.trim()))
+        })
+        .collect();
+
+    for node in placeholders {
+        while let Some(child) = node.first_child() {
+            child.detach();
+        }
+        let mut ast = node.data_mut();
+        let sourcepos_attr = if sourcepos {
+            format!(" data-sourcepos=\"{}\"", ast.sourcepos)
+        } else {
+            String::new()
+        };
+        ast.value = NodeValue::Raw(format!(
+            "<nav class=\"toc\"{}>\n{}</nav>\n",
+            sourcepos_attr,
+            toc_html(headings)
+        ));
+    }
+}
+
+/// Keeps the `data-sourcepos` of highlighted code blocks
+///
+/// The syntect adapter drops the attributes comrak passes for the `<pre>` tag when it
+/// highlights with CSS classes, so they are added back here.
+struct SourceposHighlighter<'a>(&'a SyntectAdapter);
+
+impl SyntaxHighlighterAdapter for SourceposHighlighter<'_> {
+    fn write_highlighted(
+        &self,
+        output: &mut dyn fmt::Write,
+        lang: Option<&str>,
+        code: &str,
+    ) -> fmt::Result {
+        self.0.write_highlighted(output, lang, code)
+    }
+
+    fn write_pre_tag(
+        &self,
+        output: &mut dyn fmt::Write,
+        attributes: HashMap<&'static str, Cow<'_, str>>,
+    ) -> fmt::Result {
+        let sourcepos = attributes.get("data-sourcepos").cloned();
+        let mut tag = String::new();
+        self.0.write_pre_tag(&mut tag, attributes)?;
+        if let Some(sourcepos) = sourcepos.filter(|_| !tag.contains("data-sourcepos")) {
+            tag.pop(); // the closing `>`
+            tag.push_str(&format!(" data-sourcepos=\"{}\">", sourcepos));
+        }
+        output.write_str(&tag)
+    }
+
+    fn write_code_tag(
+        &self,
+        output: &mut dyn fmt::Write,
+        attributes: HashMap<&'static str, Cow<'_, str>>,
+    ) -> fmt::Result {
+        self.0.write_code_tag(output, attributes)
+    }
+}
+
+/// Returns the language of a fenced code block from its info string
+pub fn fence_language(info: &str) -> Option<String> {
+    info.split_whitespace().next().map(str::to_lowercase)
+}
+
+/// Replaces diagram fences with their rendered HTML
+///
+/// ` ```mermaid ` fences become containers the page script draws into; ` ```dot ` and
+/// ` ```plantuml ` fences are rendered to SVG here by the locally installed tool. The
+/// output is inserted verbatim, since the tag filter would otherwise mangle SVG `<title>`s.
+/// With `sourcepos`, the diagram's container carries the fence's source position like
+/// any other block.
+fn replace_diagram_fences<'a>(root: &'a AstNode<'a>, sourcepos: bool) {
+    for node in root.descendants() {
+        let mut ast = node.data_mut();
+        let NodeValue::CodeBlock(block) = &ast.value else {
+            continue;
+        };
+        let Some(language) = fence_language(&block.info) else {
+            continue;
+        };
+
+        let html = if language == "mermaid" {
+            // The source stays in the container, so it's still readable if the diagram
+            // can't be drawn
+            format!(
+                "<div class=\"mermaid-diagram\">\n<pre class=\"diagram-source\"><code>{}</code></pre>\n</div>\n",
+                escape_html(&block.literal)
+            )
+        } else if let Some(tool) = DiagramTool::for_language(&language) {
+            render_diagram_html(tool, &block.literal)
+        } else {
+            continue;
+        };
+        let html = if sourcepos {
+            html.replacen(
+                "<div",
+                &format!("<div data-sourcepos=\"{}\"", ast.sourcepos),
+                1,
+            )
+        } else {
+            html
+        };
+        ast.value = NodeValue::Raw(html);
+    }
+}
+
+/// Maps the extension settings onto comrak's options
+fn comrak_options(extensions: &Extensions) -> Options<'static> {
     let mut options = Options::default();
 
     // Enable GitHub-flavored markdown extensions
-    options.extension.strikethrough = true;
-    options.extension.table = true;
-    options.extension.autolink = true;
-    options.extension.tasklist = true;
-    options.extension.tagfilter = true;
-    options.extension.superscript = false;
-    options.extension.header_ids = Some(String::new());
-    options.extension.footnotes = false;
-    options.extension.description_lists = false;
-    options.extension.front_matter_delimiter = None;
-    options.extension.alerts = true;
+    options.extension.strikethrough = extensions.strikethrough;
+    options.extension.table = extensions.table;
+    options.extension.autolink = extensions.autolink;
+    options.extension.tasklist = extensions.tasklist;
+    options.extension.tagfilter = extensions.tagfilter;
+    options.extension.header_ids = extensions.header_ids.then(String::new);
+    options.extension.footnotes = extensions.footnotes;
+    options.extension.alerts = extensions.alerts;
+
+    // Optional extensions beyond GitHub's own (front matter is handled before parsing)
+    options.extension.description_lists = extensions.description_lists;
+    options.extension.superscript = extensions.superscript;
+    options.extension.math_dollars = extensions.math;
+    options.extension.math_code = extensions.math;
+    options.extension.wikilinks_title_after_pipe = extensions.wikilinks;
+    options.extension.underline = extensions.underline;
+    options.extension.spoiler = extensions.spoiler;
+    options.extension.multiline_block_quotes = extensions.multiline_block_quotes;
 
     // Configure rendering options
     options.render.github_pre_lang = true;
@@ -42,22 +581,7 @@ pub fn convert_markdown(content: &str) -> String {
     options.parse.smart = false;
     options.parse.default_info_string = None;
 
-    // Set up syntax highlighting with custom gh-dark theme (bundled at compile time)
-    const THEME_DATA: &[u8] = include_bytes!("../assets/gh-dark.tmTheme");
-
-    let mut theme_set = ThemeSet::new();
-    let theme = ThemeSet::load_from_reader(&mut Cursor::new(THEME_DATA))
-        .expect("Failed to load bundled gh-dark theme");
-    theme_set.themes.insert("gh-dark".to_string(), theme);
-
-    let adapter = SyntectAdapterBuilder::new()
-        .theme_set(theme_set)
-        .theme("gh-dark")
-        .build();
-    let mut plugins = Plugins::default();
-    plugins.render.codefence_syntax_highlighter = Some(&adapter);
-
-    markdown_to_html_with_plugins(content, &options, &plugins)
+    options
 }
 
 #[cfg(test)]
@@ -95,6 +619,285 @@ mod tests {
         assert!(html.contains("main"), "HTML should contain 'main'");
     }
 
+    #[test]
+    fn test_code_block_uses_classes() {
+        let html = convert_markdown("```rust\nfn main() {}\n```");
+        assert!(html.contains("<pre class=\"syntax-highlighting\">"));
+        assert!(html.contains("class=\"hl-"));
+        assert!(!html.contains("style=\"color"));
+    }
+
+    #[test]
+    fn test_bundled_syntaxes() {
+        let syntax_set = load_syntax_set(None);
+        for token in [
+            "ts",
+            "tsx",
+            "vue",
+            "svelte",
+            "terraform",
+            "nix",
+            "zig",
+            "dockerfile",
+            "toml",
+        ] {
+            assert!(
+                syntax_set.find_syntax_by_token(token).is_some(),
+                "No syntax for '{}'",
+                token
+            );
+        }
+
+        let html = convert_markdown("```ts\nconst x: number = 1;\n```");
+        assert!(html.contains("hl-source hl-ts"));
+    }
+
+    #[test]
+    fn test_unknown_language_is_plain_text() {
+        let html = convert_markdown("```nosuchlang\nlet <b>x</b>\n```");
+        assert!(html.contains("&lt;b&gt;x&lt;/b&gt;"));
+        assert!(!html.contains("<b>"));
+    }
+
+    #[test]
+    fn test_user_syntaxes() {
+        let dir = std::env::temp_dir().join(format!("mdview-syntaxes-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("ping.sublime-syntax"),
+            "%YAML 1.2\n---\nname: Ping\nfile_extensions: [ping]\nscope: source.ping\ncontexts:\n  main:\n    - match: '\\bping\\b'\n      scope: keyword.control.ping\n",
+        )
+        .unwrap();
+        // A broken file is skipped without losing the others
+        fs::write(dir.join("broken.sublime-syntax"), "not: [valid").unwrap();
+
+        let adapter = highlighter_for(load_syntax_set(Some(&dir)));
+        let html = convert_markdown_with_highlighter("```ping\nping pong\n```", &adapter);
+        assert!(html.contains("<span class=\"hl-keyword hl-control hl-ping\">ping</span>"));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_extensions_can_be_disabled() {
+        let md = "~~gone~~\n\n| a |\n|---|\n| b |\n";
+        let disabled = Extensions {
+            strikethrough: false,
+            table: false,
+            ..Extensions::default()
+        };
+
+        let html = convert_markdown_with(md, &disabled, highlighter());
+        assert!(!html.contains("<del>"));
+        assert!(!html.contains("<table>"));
+
+        let html = convert_markdown_with(md, &Extensions::default(), highlighter());
+        assert!(html.contains("<del>gone</del>"));
+        assert!(html.contains("<table>"));
+    }
+
+    /// Renders `md` with one extension switched on and then off
+    fn render_toggled(md: &str, name: &str) -> (String, String) {
+        let mut extensions = Extensions::default();
+        extensions.set(name, true).unwrap();
+        let on = convert_markdown_with(md, &extensions, highlighter());
+        extensions.set(name, false).unwrap();
+        let off = convert_markdown_with(md, &extensions, highlighter());
+        (on, off)
+    }
+
+    #[test]
+    fn test_footnotes() {
+        let (on, off) = render_toggled("Text[^1]\n\n[^1]: The note\n", "footnotes");
+        assert!(on.contains("<section class=\"footnotes\""));
+        assert!(on.contains("data-footnote-ref"));
+        assert!(!off.contains("footnotes"));
+        assert!(Extensions::default().footnotes);
+    }
+
+    #[test]
+    fn test_front_matter() {
+        let md = "---\ntitle: Notes\n---\n\n# Body\n";
+        let (on, off) = render_toggled(md, "front_matter");
+        assert!(!on.contains("title: Notes"));
+        assert!(on.contains("Body</h1>"));
+        assert!(off.contains("title: Notes"));
+    }
+
+    #[test]
+    fn test_front_matter_table_and_title() {
+        let md = "+++\ntitle = \"Guide\"\nweight = 3\n+++\n\n# Body\n";
+        let rendered = render_document(md);
+        assert_eq!(rendered.title.as_deref(), Some("Guide"));
+        assert!(rendered
+            .html
+            .starts_with("<details class=\"front-matter\" open>"));
+        assert!(rendered.html.contains("<th>weight</th>\n<td>3</td>"));
+        assert!(!rendered.html.contains("+++"));
+
+        let rendered = render_document("# No metadata\n");
+        assert_eq!(rendered.title, None);
+        assert!(!rendered.html.contains("front-matter"));
+    }
+
+    #[test]
+    fn test_headings_match_rendered_ids() {
+        let md = "# Intro\n\n## Set *up* `mdview`\n\n### Intro\n\nText\n\n## Intro\n";
+        let rendered = render_document(md);
+        let headings: Vec<(u8, &str, &str)> = rendered
+            .headings
+            .iter()
+            .map(|h| (h.level, h.text.as_str(), h.anchor.as_str()))
+            .collect();
+        assert_eq!(
+            headings,
+            [
+                (1, "Intro", "intro"),
+                (2, "Set up mdview", "set-up-mdview"),
+                (3, "Intro", "intro-1"),
+                (2, "Intro", "intro-2"),
+            ]
+        );
+        for heading in &rendered.headings {
+            assert!(rendered
+                .html
+                .contains(&format!("id=\"{}\"", heading.anchor)));
+        }
+    }
+
+    #[test]
+    fn test_toc_placeholders() {
+        let md = "# Guide\n\n[TOC]\n\n## Install\n\n> [[_TOC_]]\n\nSee [TOC] below.\n";
+        let toc = "<nav class=\"toc\">\n<ul>\n<li><a href=\"#guide\">Guide</a>\n\
+                   <ul>\n<li><a href=\"#install\">Install</a></li>\n</ul>\n</li>\n</ul>\n</nav>\n";
+        for wikilinks in [false, true] {
+            let extensions = Extensions {
+                wikilinks,
+                ..Extensions::default()
+            };
+            let html = render_document_with(md, &extensions, highlighter()).html;
+            assert_eq!(html.matches(toc).count(), 2, "{}", html);
+            assert!(html.contains("<p>See [TOC] below.</p>"));
+        }
+
+        // Without heading ids there's nothing to link to
+        let extensions = Extensions {
+            header_ids: false,
+            ..Extensions::default()
+        };
+        let html = render_document_with(md, &extensions, highlighter()).html;
+        assert!(html.contains("<p>[TOC]</p>"));
+
+        let html = render_preview("# Guide\n\n[TOC]\n").html;
+        assert!(
+            html.contains("<nav class=\"toc\" data-sourcepos=\"3:1-3:5\">"),
+            "{}",
+            html
+        );
+    }
+
+    #[test]
+    fn test_preview_has_source_positions() {
+        let md = "---\ntitle: x\n---\n# Title\n\nFirst\nparagraph\n\n```rust\nlet x = 1;\n```\n";
+        let html = render_preview(md).html;
+        assert!(html.contains("<h1 data-sourcepos=\"4:1-4:7\">"));
+        assert!(html.contains("<p data-sourcepos=\"6:1-7:9\">"));
+        assert!(html.contains("data-sourcepos=\"9:1-11:3\""));
+
+        assert!(!render_document(md).html.contains("data-sourcepos"));
+
+        // Diagrams are blocks too, whether or not they could be drawn
+        let md = "```mermaid\ngraph TD\n```\n\n```dot\ndigraph {\n```\n";
+        let html = render_preview(md).html;
+        assert!(html.contains("<div data-sourcepos=\"1:1-3:3\" class=\"mermaid-diagram\">"));
+        assert!(html.contains("<div data-sourcepos=\"5:1-7:3\" class=\"diagram diagram-"));
+    }
+
+    #[test]
+    fn test_description_lists() {
+        let (on, off) = render_toggled("Term\n\n: Definition\n", "description_lists");
+        assert!(on.contains("<dl>"));
+        assert!(on.contains("<dt>Term</dt>"));
+        assert!(!off.contains("<dl>"));
+        assert!(!Extensions::default().description_lists);
+    }
+
+    #[test]
+    fn test_superscript() {
+        let (on, off) = render_toggled("E = mc^2^\n", "superscript");
+        assert!(on.contains("<sup>2</sup>"));
+        assert!(off.contains("mc^2^"));
+    }
+
+    #[test]
+    fn test_math() {
+        let md = "Inline $x^2$\n\n$$\ny = mx + b\n$$\n\n```math\na^2\n```\n";
+        let (on, off) = render_toggled(md, "math");
+        assert!(on.contains("data-math-style=\"inline\""));
+        assert!(on.contains("data-math-style=\"display\""));
+        assert!(!on.contains("language-math"));
+        assert!(off.contains("Inline $x^2$"));
+    }
+
+    #[test]
+    fn test_wikilinks() {
+        let (on, off) = render_toggled("See [[Setup Guide|setup]]\n", "wikilinks");
+        assert!(on.contains("<a href=\"Setup%20Guide\" data-wikilink=\"true\">setup</a>"));
+        assert!(off.contains("[[Setup Guide|setup]]"));
+    }
+
+    #[test]
+    fn test_underline() {
+        let (on, off) = render_toggled("__under__\n", "underline");
+        assert!(on.contains("<u>under</u>"));
+        assert!(off.contains("<strong>under</strong>"));
+    }
+
+    #[test]
+    fn test_spoiler() {
+        let (on, off) = render_toggled("||hidden||\n", "spoiler");
+        assert!(on.contains("<span class=\"spoiler\">hidden</span>"));
+        assert!(off.contains("||hidden||"));
+    }
+
+    #[test]
+    fn test_multiline_block_quotes() {
+        let md = ">>>\nFirst\n\nSecond\n>>>\n";
+        let (on, off) = render_toggled(md, "multiline_block_quotes");
+        assert!(on.contains("<blockquote>\n<p>First</p>\n<p>Second</p>\n</blockquote>"));
+        // Without the extension each `>>>` is three empty nested quotes
+        assert!(off.contains("</blockquote>\n<p>First</p>"));
+    }
+
+    #[test]
+    fn test_unknown_extension_name() {
+        let mut extensions = Extensions::default();
+        assert!(extensions.set("emoji", true).is_err());
+        for name in Extensions::NAMES {
+            assert!(extensions.set(name, true).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_mermaid_fence_becomes_diagram() {
+        let md = "```mermaid\ngraph TD\n  A --> B<br>\n```\n\n```rust\nfn main() {}\n```\n";
+        let html = convert_markdown(md);
+        assert!(html.contains(
+            "<div class=\"mermaid-diagram\">\n<pre class=\"diagram-source\"><code>graph TD\n  A --&gt; B&lt;br&gt;\n</code></pre>\n</div>"
+        ));
+        assert!(!html.contains("language-mermaid"));
+        // Other fences are still highlighted as usual
+        assert!(html.contains("hl-source hl-rust"));
+    }
+
+    #[test]
+    fn test_diagram_fences_are_not_highlighted() {
+        let html = convert_markdown("```dot\ndigraph { a -> b }\n```\n");
+        assert!(html.contains("<div class=\"diagram diagram-graphviz\">"));
+        assert!(!html.contains("language-dot"));
+    }
+
     #[test]
     fn test_strikethrough() {
         let md = "~~strikethrough~~";
@@ -102,6 +905,19 @@ mod tests {
         assert!(html.contains("<del>") || html.contains("strikethrough"));
     }
 
+    #[test]
+    fn test_highlighter_is_shared() {
+        assert!(std::ptr::eq(highlighter(), highlighter()));
+
+        // Rendering from several threads at once uses the same instance
+        let handles: Vec<_> = (0..4)
+            .map(|_| std::thread::spawn(|| convert_markdown("```rust\nlet x = 1;\n```")))
+            .collect();
+        for handle in handles {
+            assert!(handle.join().unwrap().contains("<pre"));
+        }
+    }
+
     #[test]
     fn test_tasklist() {
         let md = "- [ ] Task 1\n- [x] Task 2";
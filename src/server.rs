@@ -1,20 +1,202 @@
 /// Module for HTTP server with Server-Sent Events (SSE) support
-use crate::markdown::convert_markdown;
-use crate::template::build_html_page;
-use crossbeam_channel::Receiver;
+use crate::assets::{is_asset_request, serve_asset};
+use crate::config::EditorConfig;
+use crate::editor::open_in_editor;
+use crate::markdown::{render_preview, RenderedDocument};
+use crate::template::build_preview_page;
+use crate::toc::linked_toc_html;
+use crate::watcher::watch_file_until;
+use crossbeam_channel::{unbounded, Receiver, RecvTimeoutError, Sender};
+use percent_encoding::percent_decode_str;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fs;
-use std::io::Write;
+use std::hash::{DefaultHasher, Hasher};
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
-use std::time::Duration;
-use tiny_http::{Header, Request, Response, Server};
+use std::time::{Duration, SystemTime};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+/// Largest request body the API accepts
+const MAX_API_BODY: u64 = 16 * 1024 * 1024;
+
+/// Page title for documents read from stdin, unless their front matter sets one
+pub const STDIN_TITLE: &str = "stdin";
+
+/// Registered subscribers of a `Broadcaster`, keyed by subscription id
+struct Subscribers<T> {
+    next_id: u64,
+    senders: HashMap<u64, Sender<T>>,
+}
+
+/// Publish/subscribe hub that fans every event out to all live subscribers
+///
+/// Each subscriber gets its own channel, so a single published event is delivered
+/// to every connected client instead of being consumed by whichever one receives first.
+pub struct Broadcaster<T> {
+    subscribers: Arc<Mutex<Subscribers<T>>>,
+}
+
+/// A live subscription to a `Broadcaster`
+///
+/// The subscription is removed from the hub when this value is dropped.
+pub struct Subscription<T> {
+    id: u64,
+    rx: Receiver<T>,
+    subscribers: Arc<Mutex<Subscribers<T>>>,
+}
+
+impl<T: Clone> Broadcaster<T> {
+    /// Creates a hub with no subscribers
+    pub fn new() -> Self {
+        Self {
+            subscribers: Arc::new(Mutex::new(Subscribers {
+                next_id: 0,
+                senders: HashMap::new(),
+            })),
+        }
+    }
+
+    /// Registers a new subscriber and returns its subscription handle
+    pub fn subscribe(&self) -> Subscription<T> {
+        let (tx, rx) = unbounded();
+        let mut subscribers = self.subscribers.lock().unwrap();
+        let id = subscribers.next_id;
+        subscribers.next_id += 1;
+        subscribers.senders.insert(id, tx);
+
+        Subscription {
+            id,
+            rx,
+            subscribers: Arc::clone(&self.subscribers),
+        }
+    }
+
+    /// Sends an event to every subscriber
+    ///
+    /// Subscribers whose receiving end has gone away are removed.
+    ///
+    /// # Returns
+    ///
+    /// The number of subscribers the event was delivered to
+    pub fn publish(&self, event: T) -> usize {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        subscribers
+            .senders
+            .retain(|_, tx| tx.send(event.clone()).is_ok());
+        subscribers.senders.len()
+    }
+
+    /// Returns the number of currently registered subscribers
+    pub fn subscriber_count(&self) -> usize {
+        self.subscribers.lock().unwrap().senders.len()
+    }
+}
+
+impl<T: Clone> Default for Broadcaster<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Subscription<T> {
+    /// Waits up to `timeout` for the next event
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        self.rx.recv_timeout(timeout)
+    }
+}
+
+impl<T> Drop for Subscription<T> {
+    fn drop(&mut self) {
+        if let Ok(mut subscribers) = self.subscribers.lock() {
+            subscribers.senders.remove(&self.id);
+        }
+    }
+}
+
+/// Events pushed to connected browsers over the SSE stream
+///
+/// Each event is sent as a single `data:` line containing its JSON encoding,
+/// tagged by `type` so the page script can dispatch on it.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum ServerEvent {
+    /// The document was re-rendered; `html` replaces the contents of `.markdown-body`
+    /// and `title`, when present, replaces the page title. `toc`, when present, replaces
+    /// the outline in the page's sidebar.
+    Update {
+        html: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        title: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        toc: Option<String>,
+    },
+    /// An editor's cursor moved; pages scroll to the block containing source `line`
+    Scroll { line: usize },
+}
+
+/// Body of a `POST /api/cursor` or `POST /api/open-editor` request
+#[derive(Debug, Deserialize)]
+struct CursorRequest {
+    /// 1-based line number in the markdown source
+    line: usize,
+}
+
+impl ServerEvent {
+    /// Formats the event as an SSE message
+    fn to_sse(&self) -> String {
+        // JSON escapes newlines, so the payload always fits on one `data:` line
+        let payload = serde_json::to_string(self).unwrap_or_default();
+        format!("data: {}\n\n", payload)
+    }
+}
+
+/// The most recently rendered page and what it was rendered from
+struct CachedPage {
+    html: String,
+    etag: String,
+    /// Hash of the markdown source, or None if the page was supplied pre-rendered
+    source_hash: Option<u64>,
+    /// Modification time of the markdown file when the cache was last validated
+    mtime: Option<SystemTime>,
+    /// Number of the newest render the page reflects; older renders finishing later
+    /// are dropped
+    render_id: u64,
+}
+
+impl CachedPage {
+    fn new(html: String, source_hash: Option<u64>, mtime: Option<SystemTime>) -> Self {
+        let etag = format!("\"{:016x}\"", hash_bytes(html.as_bytes()));
+        Self {
+            html,
+            etag,
+            source_hash,
+            mtime,
+            render_id: 0,
+        }
+    }
+}
 
 /// HTTP server with markdown rendering and SSE live reload
+///
+/// Rendering is driven by the file watcher: the page is re-rendered once per change
+/// and every request is served from the cache.
 pub struct MarkdownServer {
-    cache: Arc<Mutex<String>>,
+    cache: Arc<Mutex<CachedPage>>,
     reload_rx: Receiver<()>,
-    base_dir: Arc<Path>,
-    file_path: Arc<Path>,
+    reload_hub: Broadcaster<ServerEvent>,
+    /// Directory images are served from, or None for documents read from stdin
+    base_dir: Option<Arc<Path>>,
+    /// The watched markdown file, or None for documents read from stdin
+    file_path: Option<Arc<Path>>,
+    /// How `POST /api/open-editor` opens the file; see `set_editor`
+    editor: Mutex<EditorConfig>,
+    /// Number handed to the next render, so renders can be ordered by when they started
+    next_render_id: AtomicU64,
 }
 
 impl MarkdownServer {
@@ -32,39 +214,212 @@ impl MarkdownServer {
         base_dir: Arc<Path>,
         file_path: Arc<Path>,
     ) -> Self {
+        let mtime = file_mtime(&file_path);
         Self {
-            cache: Arc::new(Mutex::new(initial_html)),
+            cache: Arc::new(Mutex::new(CachedPage::new(initial_html, None, mtime))),
             reload_rx,
-            base_dir,
-            file_path,
+            reload_hub: Broadcaster::new(),
+            base_dir: Some(base_dir),
+            file_path: Some(file_path),
+            editor: Mutex::new(EditorConfig::default()),
+            next_render_id: AtomicU64::new(1),
+        }
+    }
+
+    /// Creates a server for a document read from stdin rather than a file
+    ///
+    /// There is nothing to watch or resolve images against: the page changes only when
+    /// `publish_content` is called, and image requests get a 404.
+    ///
+    /// # Arguments
+    ///
+    /// * `initial_html` - The initial HTML content to serve
+    pub fn for_stdin(initial_html: String) -> Self {
+        // No file watcher ever sends on this channel, so the reload forwarder exits at once
+        let (_, reload_rx) = unbounded();
+        Self {
+            cache: Arc::new(Mutex::new(CachedPage::new(initial_html, None, None))),
+            reload_rx,
+            reload_hub: Broadcaster::new(),
+            base_dir: None,
+            file_path: None,
+            editor: Mutex::new(EditorConfig::default()),
+            next_render_id: AtomicU64::new(1),
+        }
+    }
+
+    /// Sets the editor command that pages can open the document's file with
+    ///
+    /// Until it is set, `POST /api/open-editor` reports that no editor is configured.
+    pub fn set_editor(&self, editor: EditorConfig) {
+        *self.editor.lock().unwrap() = editor;
+    }
+
+    /// Returns the number of browser connections currently listening for reloads
+    pub fn subscriber_count(&self) -> usize {
+        self.reload_hub.subscriber_count()
+    }
+
+    /// Starts a background thread that re-renders and publishes the document on every
+    /// reload signal from the file watcher
+    ///
+    /// The thread exits once the watcher's sending half of the channel is dropped.
+    pub fn spawn_reload_forwarder(self: &Arc<Self>) {
+        let server = Arc::clone(self);
+        std::thread::spawn(move || server.forward_reloads());
+    }
+
+    /// Re-renders the document on every reload signal from the file watcher and
+    /// pushes the new content to every SSE subscriber
+    ///
+    /// Blocks until the watcher's sending half of the channel is dropped.
+    fn forward_reloads(&self) {
+        for () in self.reload_rx.iter() {
+            self.publish_if_changed();
+        }
+    }
+
+    /// Re-renders the document and pushes it to every SSE subscriber if its content changed
+    fn publish_if_changed(&self) {
+        if let Some(file_path) = &self.file_path {
+            self.refresh_cache(file_path);
         }
     }
 
-    /// Refreshes the cached HTML content by reading and rendering the markdown file
+    /// Renders markdown supplied directly (rather than read from the file) and pushes
+    /// it to every SSE subscriber if it differs from what is being served
+    ///
+    /// For a file-backed document this overrides the file's content until the file is
+    /// next saved or `revert_content` is called.
+    ///
+    /// # Arguments
+    ///
+    /// * `content` - The raw markdown content
+    pub fn publish_content(&self, content: &str) {
+        // Keep the file's current mtime, so only a real save counts as a change
+        let mtime = self.file_path.as_deref().and_then(file_mtime);
+        self.update_cache(content, mtime, self.start_render());
+    }
+
+    /// Drops content set by `publish_content` and goes back to the file on disk
+    ///
+    /// # Returns
+    ///
+    /// False if there is no file to go back to, as for documents read from stdin
+    pub fn revert_content(&self) -> bool {
+        if self.file_path.is_none() {
+            return false;
+        }
+        self.publish_if_changed();
+        true
+    }
+
+    /// Sends a rendered document to every SSE subscriber
+    fn publish(&self, rendered: &RenderedDocument) {
+        self.reload_hub.publish(ServerEvent::Update {
+            toc: Some(linked_toc_html(&rendered.headings)),
+            html: rendered.html.clone(),
+            title: rendered.title.clone(),
+        });
+    }
+
+    /// Numbers a render that is about to start
+    fn start_render(&self) -> u64 {
+        self.next_render_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Checks whether the file was modified since the cache was last validated
+    ///
+    /// This is a cheap fallback for changes the file watcher missed.
+    fn is_stale(&self) -> bool {
+        let Some(file_path) = &self.file_path else {
+            return false;
+        };
+        let mtime = file_mtime(file_path);
+        self.cache.lock().map(|c| c.mtime != mtime).unwrap_or(true)
+    }
+
+    /// Refreshes the cached HTML content by reading and rendering the markdown file, and
+    /// pushes it to every SSE subscriber
+    ///
+    /// Rendering is skipped when the file's content hash matches what the cache was
+    /// built from.
     ///
     /// # Arguments
     ///
     /// * `file_path` - Path to the markdown file to read and render
-    pub fn refresh_cache(&self, file_path: &Path) {
+    ///
+    /// # Returns
+    ///
+    /// The rendered markdown body and page title, or None if the file could not be read,
+    /// is unchanged or was overtaken by a newer render
+    pub fn refresh_cache(&self, file_path: &Path) -> Option<RenderedDocument> {
+        let render_id = self.start_render();
+        let mtime = file_mtime(file_path);
         match fs::read_to_string(file_path) {
-            Ok(content) => {
-                let html_content = convert_markdown(&content);
-                let filename = file_path
-                    .file_name()
-                    .and_then(|n| n.to_str())
-                    .unwrap_or("Markdown");
-                let full_html = build_html_page(&html_content, filename);
-
-                if let Ok(mut cache) = self.cache.lock() {
-                    *cache = full_html;
-                }
-            }
+            Ok(content) => self.update_cache(&content, mtime, render_id),
             Err(e) => {
                 eprintln!("Error reading file: {}", e);
+                None
             }
         }
     }
 
+    /// Renders markdown into the cache and pushes it to every SSE subscriber, unless it
+    /// is what the cache was built from
+    ///
+    /// Renders run on the thread that asked for them, so a slow one (e.g. waiting on a
+    /// diagram tool) can finish after a newer one. Its result is then dropped, so the
+    /// page never goes back to older content.
+    ///
+    /// # Arguments
+    ///
+    /// * `content` - The raw markdown content
+    /// * `mtime` - Modification time of the file the content was read from, if any
+    /// * `render_id` - Number from `start_render`, taken before `content` was read
+    ///
+    /// # Returns
+    ///
+    /// The rendered markdown body and page title, or None if the content is unchanged or
+    /// a newer render finished first
+    fn update_cache(
+        &self,
+        content: &str,
+        mtime: Option<SystemTime>,
+        render_id: u64,
+    ) -> Option<RenderedDocument> {
+        let source_hash = hash_bytes(content.as_bytes());
+        if let Ok(mut cache) = self.cache.lock() {
+            if cache.source_hash == Some(source_hash) {
+                cache.mtime = mtime;
+                // Renders of other content that started earlier are now out of date
+                cache.render_id = cache.render_id.max(render_id);
+                return None;
+            }
+        }
+
+        let mut rendered = render_preview(content);
+        let title = self.title_for(&rendered);
+        let full_html = build_preview_page(&rendered, &title);
+        rendered.title = Some(title);
+
+        let mut cache = self.cache.lock().ok()?;
+        if cache.render_id > render_id {
+            return None;
+        }
+        *cache = CachedPage::new(full_html, Some(source_hash), mtime);
+        cache.render_id = render_id;
+        // Published under the lock, so subscribers get updates in the cache's order
+        self.publish(&rendered);
+        Some(rendered)
+    }
+
+    /// Returns the page title for a rendered version of this server's document
+    fn title_for(&self, rendered: &RenderedDocument) -> String {
+        let file_path = self.file_path.as_deref().unwrap_or(Path::new(STDIN_TITLE));
+        page_title(rendered, file_path)
+    }
+
     /// Handles an HTTP request
     ///
     /// Routes requests to either serve HTML content, handle SSE connections, or serve image files
@@ -74,191 +429,265 @@ impl MarkdownServer {
     /// * `request` - The incoming HTTP request
     pub fn handle_request(&self, request: Request) {
         let url = request.url().to_string();
+        self.handle_path(request, &url);
+    }
 
-        if url == "/events" {
+    /// Handles an HTTP request as if it were made for `path`
+    ///
+    /// Used when the server is mounted under a prefix (e.g. `/doc/<id>`) and the caller
+    /// has already stripped it from the request URL.
+    ///
+    /// # Arguments
+    ///
+    /// * `request` - The incoming HTTP request
+    /// * `path` - The request path relative to this server's mount point
+    pub fn handle_path(&self, request: Request, path: &str) {
+        let (route, _) = split_query(path);
+        if is_asset_request(route) {
+            serve_asset(request, route);
+        } else if let Some(endpoint) = route.strip_prefix("/api/") {
+            self.handle_api(request, endpoint);
+        } else if is_event_stream_request(path) {
             self.handle_sse(request);
-        } else if Self::is_image_request(&url) {
-            self.handle_image(request, &url);
+        } else if is_image_request(route) {
+            self.handle_image(request, route);
         } else {
             self.handle_html(request);
         }
     }
 
     /// Handles regular HTML requests by serving the cached content
+    ///
+    /// Supports conditional requests: if the browser's `If-None-Match` matches the
+    /// current ETag, a bodyless 304 is returned instead of the page.
     fn handle_html(&self, request: Request) {
-        let html = self.cache.lock().unwrap().clone();
+        if self.is_stale() {
+            self.publish_if_changed();
+        }
+
+        let (html, etag) = {
+            let cache = self.cache.lock().unwrap();
+            (cache.html.clone(), cache.etag.clone())
+        };
+
+        let etag_header = Header::from_bytes(&b"ETag"[..], etag.as_bytes()).unwrap();
+        let cache_header = Header::from_bytes(&b"Cache-Control"[..], &b"no-cache"[..]).unwrap();
+
+        if Self::etag_matches(&request, &etag) {
+            let response = Response::empty(304)
+                .with_header(etag_header)
+                .with_header(cache_header);
+            let _ = request.respond(response);
+            return;
+        }
 
         let response = Response::from_string(html)
             .with_header(
                 Header::from_bytes(&b"Content-Type"[..], &b"text/html; charset=utf-8"[..]).unwrap(),
             )
-            .with_header(Header::from_bytes(&b"Cache-Control"[..], &b"no-cache"[..]).unwrap());
+            .with_header(etag_header)
+            .with_header(cache_header);
 
         let _ = request.respond(response);
     }
 
-    /// Checks if a URL path is requesting an image file
-    fn is_image_request(url: &str) -> bool {
-        let lower = url.to_lowercase();
-        lower.ends_with(".png")
-            || lower.ends_with(".jpg")
-            || lower.ends_with(".jpeg")
-            || lower.ends_with(".gif")
-            || lower.ends_with(".svg")
-            || lower.ends_with(".webp")
-            || lower.ends_with(".bmp")
-            || lower.ends_with(".ico")
-    }
-
-    /// Maps file extensions to MIME types for image serving
-    fn get_content_type(path: &Path) -> &'static str {
-        match path
-            .extension()
-            .and_then(|s| s.to_str())
-            .map(|s| s.to_lowercase())
-            .as_deref()
-        {
-            Some("png") => "image/png",
-            Some("jpg") | Some("jpeg") => "image/jpeg",
-            Some("gif") => "image/gif",
-            Some("svg") => "image/svg+xml",
-            Some("webp") => "image/webp",
-            Some("bmp") => "image/bmp",
-            Some("ico") => "image/x-icon",
-            _ => "application/octet-stream",
-        }
-    }
-
-    /// Safely resolves an image path relative to the base directory
-    ///
-    /// Returns None if the path is invalid or attempts directory traversal
-    fn resolve_image_path(&self, url_path: &str) -> Option<PathBuf> {
-        // Remove leading slash
-        let path_str = url_path.trim_start_matches('/');
-
-        // Prevent empty paths
-        if path_str.is_empty() {
-            return None;
-        }
-
-        // Construct the full path
-        let full_path = self.base_dir.join(path_str);
-
-        // Canonicalize both paths to resolve .. and symlinks
-        let canonical_full = match full_path.canonicalize() {
-            Ok(p) => p,
-            Err(_) => return None, // File doesn't exist or can't be accessed
-        };
+    /// Checks whether the request's `If-None-Match` header matches the given ETag
+    fn etag_matches(request: &Request, etag: &str) -> bool {
+        request
+            .headers()
+            .iter()
+            .find(|h| h.field.equiv("If-None-Match"))
+            .map(|h| {
+                h.value.as_str().split(',').any(|candidate| {
+                    let candidate = candidate.trim();
+                    candidate == "*" || candidate.trim_start_matches("W/") == etag
+                })
+            })
+            .unwrap_or(false)
+    }
 
-        let canonical_base = match self.base_dir.canonicalize() {
-            Ok(p) => p,
-            Err(_) => return None,
-        };
+    /// Handles requests to the editor integration API under `/api/`
+    ///
+    /// JSON bodies must be sent as `application/json`, which browsers won't do
+    /// cross-origin without a preflight this server doesn't answer, so other web pages
+    /// can't drive the API. Only clients on this machine may call it at all, since it
+    /// can launch the editor and has no other authentication; requests from elsewhere
+    /// (when bound to a non-loopback address) get 403.
+    ///
+    /// * `POST /api/cursor` with `{"line": N}` scrolls every open page to source line N
+    /// * `PUT /api/content` with raw markdown shows it in place of the file until the
+    ///   file is saved (`PUT` always needs a preflight cross-origin, so any content type
+    ///   is accepted)
+    /// * `DELETE /api/content` goes back to the file's content
+    /// * `POST /api/open-editor` with `{"line": N}` opens the file at line N with the
+    ///   configured editor command
+    fn handle_api(&self, mut request: Request, endpoint: &str) {
+        if !is_local_peer(request.remote_addr()) {
+            return respond_status(request, 403, "The API only accepts local connections");
+        }
 
-        // Ensure the resolved path is within base_dir (prevents traversal)
-        if !canonical_full.starts_with(&canonical_base) {
-            eprintln!("Security: Blocked path traversal attempt: {}", url_path);
-            return None;
+        match (request.method(), endpoint) {
+            (Method::Put, "content") => match read_text(&mut request) {
+                Ok(content) => {
+                    self.publish_content(&content);
+                    respond_status(request, 204, "");
+                }
+                Err((status, message)) => respond_status(request, status, &message),
+            },
+            (Method::Delete, "content") => {
+                if self.revert_content() {
+                    respond_status(request, 204, "");
+                } else {
+                    respond_status(request, 409, "There is no file to revert to");
+                }
+            }
+            (_, "content") => respond_status(request, 405, "Use PUT or DELETE"),
+            (Method::Post, "cursor") => match read_json::<CursorRequest>(&mut request) {
+                Ok(cursor) if cursor.line > 0 => {
+                    self.reload_hub
+                        .publish(ServerEvent::Scroll { line: cursor.line });
+                    respond_status(request, 204, "");
+                }
+                Ok(_) => respond_status(request, 400, "Lines are numbered from 1"),
+                Err((status, message)) => respond_status(request, status, &message),
+            },
+            (_, "cursor") => respond_status(request, 405, "Use POST"),
+            (Method::Post, "open-editor") => match read_json::<CursorRequest>(&mut request) {
+                Ok(cursor) if cursor.line > 0 => match &self.file_path {
+                    Some(file_path) => {
+                        let editor = self.editor.lock().unwrap().clone();
+                        match open_in_editor(&editor, file_path, cursor.line) {
+                            Ok(()) => respond_status(request, 204, ""),
+                            Err(message) => respond_status(request, 500, &message),
+                        }
+                    }
+                    None => respond_status(request, 409, "There is no file to open"),
+                },
+                Ok(_) => respond_status(request, 400, "Lines are numbered from 1"),
+                Err((status, message)) => respond_status(request, status, &message),
+            },
+            (_, "open-editor") => respond_status(request, 405, "Use POST"),
+            _ => respond_not_found(request),
         }
+    }
 
-        // Verify it's a file (not a directory)
-        if !canonical_full.is_file() {
-            return None;
+    /// Handles image file requests, resolving paths relative to the document's directory
+    fn handle_image(&self, request: Request, url_path: &str) {
+        match &self.base_dir {
+            Some(base_dir) => serve_image(request, base_dir, url_path),
+            None => respond_not_found(request),
         }
+    }
 
-        Some(canonical_full)
+    /// Handles Server-Sent Events (SSE) connections for live reload
+    ///
+    /// This function keeps the connection open and sends the freshly rendered document
+    /// whenever the file changes. It also sends periodic keepalive messages to prevent
+    /// connection timeouts.
+    fn handle_sse(&self, request: Request) {
+        stream_events(request, &self.reload_hub);
     }
+}
 
-    /// Handles image file requests
-    fn handle_image(&self, request: Request, url_path: &str) {
-        // Resolve path safely
-        let image_path = match self.resolve_image_path(url_path) {
-            Some(path) => path,
-            None => {
-                // Return 404 for invalid/missing files
-                let response = Response::from_string("404 Not Found")
-                    .with_status_code(404)
-                    .with_header(
-                        Header::from_bytes(&b"Content-Type"[..], &b"text/plain"[..]).unwrap(),
-                    );
-                let _ = request.respond(response);
-                return;
-            }
-        };
+/// A rendered document whose file is being watched for changes
+///
+/// Dropping it stops the file watcher; the server itself lives on for as long as
+/// requests still hold it.
+pub struct WatchedDocument {
+    pub server: Arc<MarkdownServer>,
+    /// Dropping this ends the document's file watcher
+    _stop_watching: Sender<()>,
+}
 
-        // Read image file as binary data
-        let image_data = match fs::read(&image_path) {
-            Ok(data) => data,
-            Err(e) => {
-                eprintln!("Error reading image file {}: {}", image_path.display(), e);
-                let response = Response::from_string("500 Internal Server Error")
-                    .with_status_code(500)
-                    .with_header(
-                        Header::from_bytes(&b"Content-Type"[..], &b"text/plain"[..]).unwrap(),
-                    );
-                let _ = request.respond(response);
-                return;
-            }
-        };
+impl WatchedDocument {
+    /// Renders a file and starts its reload forwarder and file watcher
+    ///
+    /// # Arguments
+    ///
+    /// * `file_path` - Full path to the markdown file
+    /// * `debounce` - Quiet period the file watcher waits for before reloading
+    ///
+    /// # Returns
+    ///
+    /// The watched document, or a message describing why the file couldn't be opened
+    pub fn open(file_path: &Path, debounce: Duration) -> Result<Self, String> {
+        let content =
+            fs::read_to_string(file_path).map_err(|e| format!("Error reading file: {}", e))?;
+        let base_dir = file_path
+            .parent()
+            .ok_or("Could not determine parent directory")?;
+        let rendered = render_preview(&content);
+        let initial_html = build_preview_page(&rendered, &page_title(&rendered, file_path));
+        let (reload_tx, reload_rx) = unbounded();
+        let server = Arc::new(MarkdownServer::new(
+            initial_html,
+            reload_rx,
+            Arc::from(base_dir),
+            Arc::from(file_path),
+        ));
+        server.spawn_reload_forwarder();
 
-        // Send response with appropriate Content-Type
-        let content_type = Self::get_content_type(&image_path);
-        let response = Response::from_data(image_data)
-            .with_header(
-                Header::from_bytes(&b"Content-Type"[..], content_type.as_bytes()).unwrap(),
-            )
-            .with_header(
-                Header::from_bytes(&b"Cache-Control"[..], &b"max-age=3600"[..]).unwrap(),
-            );
+        let (stop_tx, stop_rx) = unbounded();
+        let watcher_file_path = file_path.to_path_buf();
+        std::thread::spawn(move || {
+            if let Err(e) = watch_file_until(watcher_file_path, reload_tx, debounce, stop_rx) {
+                eprintln!("File watcher error: {}", e);
+            }
+        });
 
-        let _ = request.respond(response);
+        Ok(Self {
+            server,
+            _stop_watching: stop_tx,
+        })
     }
+}
 
-    /// Handles Server-Sent Events (SSE) connections for live reload
-    ///
-    /// This function keeps the connection open and sends reload events when the file changes.
-    /// It also sends periodic keepalive messages to prevent connection timeouts.
-    fn handle_sse(&self, request: Request) {
-        // Clone the receiver for this SSE connection
-        let reload_rx = self.reload_rx.clone();
+/// Streams events from a hub to a client as Server-Sent Events
+///
+/// Blocks for as long as the client stays connected, sending periodic keepalive
+/// messages so the page can detect dead connections.
+///
+/// # Arguments
+///
+/// * `request` - The incoming `EventSource` request
+/// * `hub` - The hub whose events are forwarded to this client
+pub fn stream_events(request: Request, hub: &Broadcaster<ServerEvent>) {
+    // Subscribe this connection to the hub; dropping it on return unsubscribes
+    let subscription = hub.subscribe();
+    println!("Browser connected ({} active)", hub.subscriber_count());
 
-        // Create SSE response headers
-        let response = Response::empty(200)
-            .with_header(
-                Header::from_bytes(&b"Content-Type"[..], &b"text/event-stream"[..]).unwrap(),
-            )
-            .with_header(Header::from_bytes(&b"Cache-Control"[..], &b"no-cache"[..]).unwrap())
-            .with_header(Header::from_bytes(&b"Connection"[..], &b"keep-alive"[..]).unwrap())
-            .with_header(
-                Header::from_bytes(&b"Access-Control-Allow-Origin"[..], &b"*"[..]).unwrap(),
-            );
+    // Create SSE response headers
+    let response = Response::empty(200)
+        .with_header(Header::from_bytes(&b"Content-Type"[..], &b"text/event-stream"[..]).unwrap())
+        .with_header(Header::from_bytes(&b"Cache-Control"[..], &b"no-cache"[..]).unwrap())
+        .with_header(Header::from_bytes(&b"Connection"[..], &b"keep-alive"[..]).unwrap())
+        .with_header(Header::from_bytes(&b"Access-Control-Allow-Origin"[..], &b"*"[..]).unwrap());
 
-        // Upgrade to a data stream
-        let mut stream = request.upgrade("text/event-stream", response);
+    // Upgrade to a data stream
+    let mut stream = request.upgrade("text/event-stream", response);
 
-        // Keep connection alive and send reload events
-        loop {
-            match reload_rx.recv_timeout(Duration::from_secs(15)) {
-                Ok(_) => {
-                    // File changed, send reload event
-                    if write!(stream, "data: reload\n\n").is_err() {
-                        // Connection closed by client
-                        break;
-                    }
-                    if stream.flush().is_err() {
-                        // Connection closed by client
-                        break;
-                    }
+    // Keep connection alive and send update events
+    loop {
+        match subscription.recv_timeout(Duration::from_secs(15)) {
+            Ok(event) => {
+                // File changed, send the re-rendered content
+                if write!(stream, "{}", event.to_sse()).is_err() {
+                    // Connection closed by client
+                    break;
                 }
-                Err(_) => {
-                    // Timeout - send keepalive as data message so client can detect it
-                    if write!(stream, "data: keepalive\n\n").is_err() {
-                        // Connection closed
-                        break;
-                    }
-                    if stream.flush().is_err() {
-                        break;
-                    }
+                if stream.flush().is_err() {
+                    // Connection closed by client
+                    break;
+                }
+            }
+            Err(_) => {
+                // Timeout - send keepalive as data message so client can detect it
+                if write!(stream, "data: keepalive\n\n").is_err() {
+                    // Connection closed
+                    break;
+                }
+                if stream.flush().is_err() {
+                    break;
                 }
             }
         }
@@ -271,6 +700,7 @@ impl MarkdownServer {
 ///
 /// # Arguments
 ///
+/// * `bind` - The address to listen on
 /// * `port` - The port to bind the server to
 /// * `server` - The MarkdownServer instance to handle requests
 ///
@@ -278,24 +708,845 @@ impl MarkdownServer {
 ///
 /// A Result indicating success or failure
 pub fn run_server(
+    bind: &str,
     port: u16,
     server: Arc<MarkdownServer>,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let http_server = Server::http(format!("127.0.0.1:{}", port))?;
+    let http_server = Server::http((bind, port))?;
+
+    // Fan reload signals out to all connected browsers
+    server.spawn_reload_forwarder();
 
     for request in http_server.incoming_requests() {
         let server = Arc::clone(&server);
 
         // Spawn a thread for each request
         std::thread::spawn(move || {
-            let url = request.url();
-            // Only refresh cache for HTML requests (not SSE or images)
-            if url != "/events" && !MarkdownServer::is_image_request(url) {
-                server.refresh_cache(&server.file_path);
-            }
             server.handle_request(request);
         });
     }
 
     Ok(())
 }
+
+/// Returns the title for a rendered document: its front matter title, or else the file name
+pub fn page_title(rendered: &RenderedDocument, file_path: &Path) -> String {
+    rendered.title.clone().unwrap_or_else(|| {
+        file_path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or("Markdown")
+            .to_string()
+    })
+}
+
+/// Finds an available port on the given address, starting from the specified port
+pub fn find_available_port(bind: &str, start_port: u16, max_attempts: u16) -> Option<u16> {
+    (start_port..=u16::MAX)
+        .take(max_attempts as usize)
+        .find(|port| TcpListener::bind((bind, *port)).is_ok())
+}
+
+/// Returns the base URL a server listening on `bind` and `port` can be reached at
+///
+/// Loopback and wildcard addresses are shown as `localhost`; anything else is used as is.
+pub fn base_url(bind: &str, port: u16) -> String {
+    let host = match bind.parse::<std::net::IpAddr>() {
+        Ok(ip) if ip.is_loopback() || ip.is_unspecified() => "localhost".to_string(),
+        Ok(std::net::IpAddr::V6(ip)) => format!("[{}]", ip),
+        _ => bind.to_string(),
+    };
+    format!("http://{}:{}", host, port)
+}
+
+/// Splits a request URL into its path and optional query string
+pub fn split_query(url: &str) -> (&str, Option<&str>) {
+    match url.split_once('?') {
+        Some((path, query)) => (path, Some(query)),
+        None => (url, None),
+    }
+}
+
+/// Checks whether a request URL asks for the live-update event stream
+///
+/// Pages open their stream at `?events` relative to their own URL, so each document
+/// gets its own stream wherever it is mounted.
+pub fn is_event_stream_request(url: &str) -> bool {
+    split_query(url).1 == Some("events")
+}
+
+/// Checks if a URL path is requesting an image file
+pub fn is_image_request(url: &str) -> bool {
+    let lower = url.to_lowercase();
+    lower.ends_with(".png")
+        || lower.ends_with(".jpg")
+        || lower.ends_with(".jpeg")
+        || lower.ends_with(".gif")
+        || lower.ends_with(".svg")
+        || lower.ends_with(".webp")
+        || lower.ends_with(".bmp")
+        || lower.ends_with(".ico")
+}
+
+/// Maps file extensions to MIME types for image serving
+pub fn get_content_type(path: &Path) -> &'static str {
+    match path
+        .extension()
+        .and_then(|s| s.to_str())
+        .map(|s| s.to_lowercase())
+        .as_deref()
+    {
+        Some("png") => "image/png",
+        Some("jpg") | Some("jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("svg") => "image/svg+xml",
+        Some("webp") => "image/webp",
+        Some("bmp") => "image/bmp",
+        Some("ico") => "image/x-icon",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Safely resolves a URL path to a file inside the base directory
+///
+/// The path is percent-decoded first. Returns None if the path is invalid, does not
+/// name a file, or attempts directory traversal.
+pub fn resolve_file(base_dir: &Path, url_path: &str) -> Option<PathBuf> {
+    let decoded = percent_decode_str(url_path).decode_utf8().ok()?;
+
+    // Remove leading slash
+    let path_str = decoded.trim_start_matches('/');
+
+    // Prevent empty paths
+    if path_str.is_empty() {
+        return None;
+    }
+
+    // Construct the full path
+    let full_path = base_dir.join(path_str);
+
+    // Canonicalize both paths to resolve .. and symlinks
+    let canonical_full = match full_path.canonicalize() {
+        Ok(p) => p,
+        Err(_) => return None, // File doesn't exist or can't be accessed
+    };
+
+    let canonical_base = match base_dir.canonicalize() {
+        Ok(p) => p,
+        Err(_) => return None,
+    };
+
+    // Ensure the resolved path is within base_dir (prevents traversal)
+    if !canonical_full.starts_with(&canonical_base) {
+        eprintln!("Security: Blocked path traversal attempt: {}", url_path);
+        return None;
+    }
+
+    // Verify it's a file (not a directory)
+    if !canonical_full.is_file() {
+        return None;
+    }
+
+    Some(canonical_full)
+}
+
+/// Serves an image file from inside `base_dir`, or a 404 if it can't be resolved
+pub fn serve_image(request: Request, base_dir: &Path, url_path: &str) {
+    // Resolve path safely
+    let image_path = match resolve_file(base_dir, url_path) {
+        Some(path) => path,
+        // Return 404 for invalid/missing files
+        None => return respond_not_found(request),
+    };
+
+    // Read image file as binary data
+    let image_data = match fs::read(&image_path) {
+        Ok(data) => data,
+        Err(e) => {
+            eprintln!("Error reading image file {}: {}", image_path.display(), e);
+            let response = Response::from_string("500 Internal Server Error")
+                .with_status_code(500)
+                .with_header(Header::from_bytes(&b"Content-Type"[..], &b"text/plain"[..]).unwrap());
+            let _ = request.respond(response);
+            return;
+        }
+    };
+
+    // Send response with appropriate Content-Type
+    let content_type = get_content_type(&image_path);
+    let response = Response::from_data(image_data)
+        .with_header(Header::from_bytes(&b"Content-Type"[..], content_type.as_bytes()).unwrap())
+        .with_header(Header::from_bytes(&b"Cache-Control"[..], &b"max-age=3600"[..]).unwrap());
+
+    let _ = request.respond(response);
+}
+
+/// Reads a request's body as JSON
+///
+/// # Errors
+///
+/// Returns the status and a message for the client if the body is too large, isn't
+/// JSON of the expected shape or wasn't sent as `application/json`
+fn read_json<T: DeserializeOwned>(request: &mut Request) -> Result<T, (u16, String)> {
+    let is_json = request
+        .headers()
+        .iter()
+        .any(|h| h.field.equiv("Content-Type") && h.value.as_str().starts_with("application/json"));
+    if !is_json {
+        return Err((400, "Expected Content-Type: application/json".to_string()));
+    }
+    let body = read_body(request)?;
+    serde_json::from_slice(&body).map_err(|e| (400, format!("Invalid request body: {}", e)))
+}
+
+/// Reads a request's body as UTF-8 text
+///
+/// # Errors
+///
+/// Returns the status and a message for the client if the body is too large, can't be
+/// read or isn't UTF-8
+fn read_text(request: &mut Request) -> Result<String, (u16, String)> {
+    let body = read_body(request)?;
+    String::from_utf8(body).map_err(|e| (400, format!("Invalid request body: {}", e)))
+}
+
+/// Reads a request's whole body, refusing bodies over `MAX_API_BODY`
+///
+/// # Errors
+///
+/// Returns 413 if the body is too large, or 400 if it can't be read
+fn read_body(request: &mut Request) -> Result<Vec<u8>, (u16, String)> {
+    let too_large = || {
+        (
+            413,
+            format!("Request body is larger than {} bytes", MAX_API_BODY),
+        )
+    };
+    if request
+        .body_length()
+        .is_some_and(|length| length as u64 > MAX_API_BODY)
+    {
+        return Err(too_large());
+    }
+
+    // One byte past the limit tells a body that is too large from one that fits exactly
+    let mut body = Vec::new();
+    request
+        .as_reader()
+        .take(MAX_API_BODY + 1)
+        .read_to_end(&mut body)
+        .map_err(|e| (400, format!("Invalid request body: {}", e)))?;
+    if body.len() as u64 > MAX_API_BODY {
+        return Err(too_large());
+    }
+    Ok(body)
+}
+
+/// Checks whether a request came from this machine
+fn is_local_peer(addr: Option<&SocketAddr>) -> bool {
+    addr.is_some_and(|addr| addr.ip().to_canonical().is_loopback())
+}
+
+/// Sends a plain-text response with the given status code
+fn respond_status(request: Request, status: u16, message: &str) {
+    let response = Response::from_string(message)
+        .with_status_code(status)
+        .with_header(Header::from_bytes(&b"Content-Type"[..], &b"text/plain"[..]).unwrap());
+    let _ = request.respond(response);
+}
+
+/// Sends a plain-text 404 response
+pub fn respond_not_found(request: Request) {
+    let response = Response::from_string("404 Not Found")
+        .with_status_code(404)
+        .with_header(Header::from_bytes(&b"Content-Type"[..], &b"text/plain"[..]).unwrap());
+    let _ = request.respond(response);
+}
+
+/// Returns the file's modification time, or None if it can't be determined
+fn file_mtime(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Hashes raw bytes for change detection, ETags and render caches
+pub fn hash_bytes(bytes: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    hasher.write(bytes);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::net::TcpStream;
+    use std::time::Instant;
+
+    /// Reserves a free local port by binding to port 0 and releasing it
+    fn free_port() -> u16 {
+        TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port()
+    }
+
+    /// A `run_server` instance serving a scratch markdown file
+    struct TestServer {
+        port: u16,
+        server: Arc<MarkdownServer>,
+        reload_tx: Sender<()>,
+        dir: PathBuf,
+        file_path: PathBuf,
+    }
+
+    impl TestServer {
+        /// Writes `content` to a scratch file and starts serving it
+        fn start(name: &str, content: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("mdview-{}-{}", name, std::process::id()));
+            fs::create_dir_all(&dir).unwrap();
+            let file_path = dir.join("test.md");
+            fs::write(&file_path, content).unwrap();
+
+            let (reload_tx, reload_rx) = unbounded();
+            let server = Arc::new(MarkdownServer::new(
+                build_preview_page(&render_preview(content), "test.md"),
+                reload_rx,
+                Arc::from(dir.as_path()),
+                Arc::from(file_path.as_path()),
+            ));
+
+            let port = free_port();
+            let running = Arc::clone(&server);
+            std::thread::spawn(move || run_server("127.0.0.1", port, running));
+
+            Self {
+                port,
+                server,
+                reload_tx,
+                dir,
+                file_path,
+            }
+        }
+
+        /// Starts serving a document as if it were read from stdin
+        fn start_stdin(name: &str, content: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("mdview-{}-{}", name, std::process::id()));
+            let server = Arc::new(MarkdownServer::for_stdin(build_preview_page(
+                &render_preview(content),
+                STDIN_TITLE,
+            )));
+
+            let port = free_port();
+            let running = Arc::clone(&server);
+            std::thread::spawn(move || run_server("127.0.0.1", port, running));
+
+            Self {
+                port,
+                server,
+                reload_tx: unbounded().0,
+                file_path: dir.join("test.md"),
+                dir,
+            }
+        }
+
+        /// Opens a connection and sends a GET request, retrying until the server is listening
+        fn send_get(&self, path: &str, extra_headers: &str) -> TcpStream {
+            let deadline = Instant::now() + Duration::from_secs(5);
+            loop {
+                match TcpStream::connect(("127.0.0.1", self.port)) {
+                    Ok(mut stream) => {
+                        write!(
+                            stream,
+                            "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n{}\r\n",
+                            path, extra_headers
+                        )
+                        .unwrap();
+                        stream
+                            .set_read_timeout(Some(Duration::from_secs(5)))
+                            .unwrap();
+                        return stream;
+                    }
+                    Err(e) if Instant::now() > deadline => panic!("Server never came up: {}", e),
+                    Err(_) => std::thread::sleep(Duration::from_millis(20)),
+                }
+            }
+        }
+
+        /// Performs a GET request and returns the raw HTTP response
+        fn get(&self, path: &str, extra_headers: &str) -> String {
+            let mut response = String::new();
+            self.send_get(path, extra_headers)
+                .read_to_string(&mut response)
+                .unwrap();
+            response
+        }
+
+        /// Sends a request with a body and returns the raw HTTP response
+        fn send(&self, method: &str, path: &str, content_type: &str, body: &str) -> String {
+            let mut stream = TcpStream::connect(("127.0.0.1", self.port)).unwrap();
+            write!(
+                stream,
+                "{} {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\
+                 Content-Type: {}\r\nContent-Length: {}\r\n\r\n{}",
+                method,
+                path,
+                content_type,
+                body.len(),
+                body
+            )
+            .unwrap();
+            stream
+                .set_read_timeout(Some(Duration::from_secs(5)))
+                .unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        }
+
+        /// Opens an event stream against the server
+        fn open_event_stream(&self) -> TcpStream {
+            self.send_get("/?events", "")
+        }
+    }
+
+    impl Drop for TestServer {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    /// Extracts the value of a response header
+    fn header_value(response: &str, name: &str) -> Option<String> {
+        let prefix = format!("{}: ", name.to_lowercase());
+        response
+            .lines()
+            .take_while(|line| !line.is_empty())
+            .find(|line| line.to_lowercase().starts_with(&prefix))
+            .map(|line| line[prefix.len()..].trim().to_string())
+    }
+
+    /// Reads from the stream until `needle` appears, panicking on timeout
+    fn read_until(stream: &mut TcpStream, needle: &str) -> String {
+        let mut received = String::new();
+        let mut buf = [0u8; 1024];
+        while !received.contains(needle) {
+            let n = stream
+                .read(&mut buf)
+                .unwrap_or_else(|e| panic!("Timed out waiting for {:?}: {}", needle, e));
+            assert!(n > 0, "Stream closed before {:?} arrived", needle);
+            received.push_str(&String::from_utf8_lossy(&buf[..n]));
+        }
+        received
+    }
+
+    fn wait_for_subscribers(server: &MarkdownServer, expected: usize) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while server.subscriber_count() != expected {
+            assert!(
+                Instant::now() < deadline,
+                "Expected {} subscribers, have {}",
+                expected,
+                server.subscriber_count()
+            );
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn test_broadcaster_delivers_to_every_subscriber() {
+        let hub = Broadcaster::new();
+        let first = hub.subscribe();
+        let second = hub.subscribe();
+
+        assert_eq!(hub.publish(7), 2);
+        assert_eq!(first.recv_timeout(Duration::from_secs(1)), Ok(7));
+        assert_eq!(second.recv_timeout(Duration::from_secs(1)), Ok(7));
+    }
+
+    #[test]
+    fn test_broadcaster_removes_dropped_subscribers() {
+        let hub = Broadcaster::new();
+        let kept = hub.subscribe();
+        let dropped = hub.subscribe();
+        assert_eq!(hub.subscriber_count(), 2);
+
+        drop(dropped);
+        assert_eq!(hub.subscriber_count(), 1);
+        assert_eq!(hub.publish(()), 1);
+        assert!(kept.recv_timeout(Duration::from_secs(1)).is_ok());
+    }
+
+    #[test]
+    fn test_base_url() {
+        assert_eq!(base_url("127.0.0.1", 6914), "http://localhost:6914");
+        assert_eq!(base_url("0.0.0.0", 6914), "http://localhost:6914");
+        assert_eq!(base_url("192.168.1.20", 80), "http://192.168.1.20:80");
+        assert_eq!(base_url("fd00::1", 6914), "http://[fd00::1]:6914");
+    }
+
+    #[test]
+    fn test_update_event_sse_format() {
+        let event = ServerEvent::Update {
+            html: "<p>a\nb</p>".to_string(),
+            title: None,
+            toc: None,
+        };
+        assert_eq!(
+            event.to_sse(),
+            "data: {\"type\":\"update\",\"html\":\"<p>a\\nb</p>\"}\n\n"
+        );
+
+        let event = ServerEvent::Update {
+            html: String::new(),
+            title: Some("Guide".to_string()),
+            toc: None,
+        };
+        assert!(event.to_sse().contains("\"title\":\"Guide\""));
+    }
+
+    #[test]
+    fn test_reload_reaches_all_event_streams() {
+        let test = TestServer::start("sse", "# Test");
+
+        let mut streams: Vec<TcpStream> = (0..3).map(|_| test.open_event_stream()).collect();
+        wait_for_subscribers(&test.server, 3);
+
+        fs::write(&test.file_path, "# Changed").unwrap();
+        test.reload_tx.send(()).unwrap();
+        for stream in &mut streams {
+            let received = read_until(stream, "Changed</a>");
+            assert!(received.contains(r#"data: {"type":"update""#));
+            assert!(received.contains("Changed</h1>"));
+            assert!(received.contains(r##""toc":"<ul>\n<li><a href=\"#changed\">Changed"##));
+        }
+    }
+
+    #[test]
+    fn test_html_served_from_cache_with_etag() {
+        let test = TestServer::start("etag", "# Cached");
+
+        let first = test.get("/", "");
+        assert!(first.starts_with("HTTP/1.1 200"));
+        assert!(first.contains("Cached</h1>"));
+        let etag = header_value(&first, "ETag").expect("HTML response should carry an ETag");
+
+        // Unchanged file: the same ETag comes back and a conditional request gets a 304
+        let second = test.get("/", "");
+        assert_eq!(header_value(&second, "ETag"), Some(etag.clone()));
+
+        let conditional = test.get("/", &format!("If-None-Match: {}\r\n", etag));
+        assert!(conditional.starts_with("HTTP/1.1 304"));
+        assert!(!conditional.contains("Cached</h1>"));
+    }
+
+    #[test]
+    fn test_serves_embedded_stylesheet() {
+        let test = TestServer::start("assets", "# Styled");
+
+        let css = test.get("/__mdview/assets/github-markdown.css", "");
+        assert!(css.starts_with("HTTP/1.1 200"));
+        assert_eq!(
+            header_value(&css, "Content-Type").as_deref(),
+            Some("text/css; charset=utf-8")
+        );
+        assert!(css.contains(".markdown-body"));
+
+        assert!(test
+            .get("/__mdview/assets/missing.css", "")
+            .starts_with("HTTP/1.1 404"));
+    }
+
+    #[test]
+    fn test_reload_signal_rerenders_cache() {
+        let test = TestServer::start("rerender", "# Before");
+        let before = header_value(&test.get("/", ""), "ETag");
+
+        fs::write(&test.file_path, "# After").unwrap();
+        test.reload_tx.send(()).unwrap();
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            let response = test.get("/", "");
+            if response.contains("After</h1>") {
+                assert_ne!(header_value(&response, "ETag"), before);
+                break;
+            }
+            assert!(Instant::now() < deadline, "Cache was never re-rendered");
+            std::thread::sleep(Duration::from_millis(20));
+        }
+    }
+
+    #[test]
+    fn test_watched_document_uses_configured_debounce() {
+        let dir = std::env::temp_dir().join(format!("mdview-debounce-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file_path = dir.join("test.md");
+        fs::write(&file_path, "# Before").unwrap();
+
+        let document = WatchedDocument::open(&file_path, Duration::from_millis(1500)).unwrap();
+        let updates = document.server.reload_hub.subscribe();
+        // Give the watcher time to start
+        std::thread::sleep(Duration::from_millis(300));
+        fs::write(&file_path, "# After").unwrap();
+
+        // Nothing is published until the file has been quiet for the whole window
+        assert!(updates.recv_timeout(Duration::from_millis(700)).is_err());
+        match updates.recv_timeout(Duration::from_secs(5)) {
+            Ok(ServerEvent::Update { html, .. }) => assert!(html.contains("After</h1>")),
+            other => panic!("Expected an update, got {:?}", other),
+        }
+
+        drop(document);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_front_matter_title_used_for_page() {
+        let test = TestServer::start("title", "# Body");
+        fs::write(&test.file_path, "---\ntitle: Install guide\n---\n# Body\n").unwrap();
+
+        let rendered = test.server.refresh_cache(&test.file_path).unwrap();
+        assert_eq!(rendered.title.as_deref(), Some("Install guide"));
+        assert!(test.get("/", "").contains("<title>Install guide</title>"));
+
+        // Without a title the file name is used, so the update resets the tab title
+        fs::write(&test.file_path, "# Body\n").unwrap();
+        let rendered = test.server.refresh_cache(&test.file_path).unwrap();
+        assert_eq!(rendered.title.as_deref(), Some("test.md"));
+    }
+
+    #[test]
+    fn test_stdin_documents_are_published() {
+        let test = TestServer::start_stdin("stdin", "# First");
+        assert!(test.get("/", "").contains("<title>stdin</title>"));
+
+        let mut stream = test.open_event_stream();
+        wait_for_subscribers(&test.server, 1);
+        test.server
+            .publish_content("---\ntitle: PR body\n---\n# Second\n");
+        let received = read_until(&mut stream, "Second");
+        assert!(received.contains("\"title\":\"PR body\""));
+        assert!(test.get("/", "").contains("Second</h1>"));
+
+        // There's no directory to resolve images against
+        assert!(test.get("/image.png", "").starts_with("HTTP/1.1 404"));
+    }
+
+    #[test]
+    fn test_cursor_api_scrolls_every_page() {
+        let test = TestServer::start("cursor", "# Title\n\nText\n");
+        assert!(test
+            .get("/", "")
+            .contains("<h1 data-sourcepos=\"1:1-1:7\">"));
+
+        let mut first = test.open_event_stream();
+        let mut second = test.open_event_stream();
+        wait_for_subscribers(&test.server, 2);
+
+        let response = test.send("POST", "/api/cursor", "application/json", "{\"line\": 3}");
+        assert!(response.starts_with("HTTP/1.1 204"), "{}", response);
+        for stream in [&mut first, &mut second] {
+            read_until(stream, "data: {\"type\":\"scroll\",\"line\":3}\n\n");
+        }
+    }
+
+    #[test]
+    fn test_cursor_api_rejects_bad_requests() {
+        let test = TestServer::start("cursor-errors", "# Title");
+        test.get("/", "");
+
+        for (method, content_type, body, status) in [
+            ("POST", "application/json", "{\"line\": 0}", "400"),
+            ("POST", "application/json", "{\"column\": 3}", "400"),
+            // Cross-site form posts can't set a JSON content type
+            ("POST", "text/plain", "{\"line\": 3}", "400"),
+            ("GET", "application/json", "", "405"),
+        ] {
+            let response = test.send(method, "/api/cursor", content_type, body);
+            assert!(
+                response.starts_with(&format!("HTTP/1.1 {}", status)),
+                "{} {}: {}",
+                method,
+                body,
+                response
+            );
+        }
+        assert!(test
+            .send("POST", "/api/unknown", "application/json", "{}")
+            .starts_with("HTTP/1.1 404"));
+    }
+
+    #[test]
+    fn test_open_editor_api() {
+        let test = TestServer::start("open-editor", "# Title\n\nText\n");
+        test.get("/", "");
+
+        // An "editor" that leaves a marker file named after the file and line
+        test.server.set_editor(EditorConfig {
+            command: Some("touch {file}.{line}".to_string()),
+        });
+        let response = test.send(
+            "POST",
+            "/api/open-editor",
+            "application/json",
+            "{\"line\": 3}",
+        );
+        assert!(response.starts_with("HTTP/1.1 204"), "{}", response);
+        let marker = test.file_path.with_extension("md.3");
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !marker.exists() {
+            assert!(Instant::now() < deadline, "Editor command didn't run");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        for (method, body, status) in [("POST", "{\"line\": 0}", "400"), ("GET", "", "405")] {
+            let response = test.send(method, "/api/open-editor", "application/json", body);
+            assert!(
+                response.starts_with(&format!("HTTP/1.1 {}", status)),
+                "{} {}: {}",
+                method,
+                body,
+                response
+            );
+        }
+
+        // Stdin documents have no file to open
+        let stdin = TestServer::start_stdin("open-editor-stdin", "# Piped");
+        stdin.get("/", "");
+        let response = stdin.send(
+            "POST",
+            "/api/open-editor",
+            "application/json",
+            "{\"line\": 1}",
+        );
+        assert!(response.starts_with("HTTP/1.1 409"), "{}", response);
+    }
+
+    #[test]
+    fn test_content_override_until_save() {
+        let test = TestServer::start("override", "# On disk\n");
+        test.get("/", "");
+
+        let mut stream = test.open_event_stream();
+        wait_for_subscribers(&test.server, 1);
+        let response = test.send("PUT", "/api/content", "text/markdown", "# Unsaved\n");
+        assert!(response.starts_with("HTTP/1.1 204"), "{}", response);
+        read_until(&mut stream, "Unsaved");
+
+        // The override isn't mistaken for a change to the file
+        assert!(test.get("/", "").contains("Unsaved</h1>"));
+
+        // Saving the file replaces the override
+        std::thread::sleep(Duration::from_millis(20));
+        fs::write(&test.file_path, "# Saved\n").unwrap();
+        test.reload_tx.send(()).unwrap();
+        read_until(&mut stream, "Saved</h1>");
+        assert!(test.get("/", "").contains("Saved</h1>"));
+    }
+
+    #[test]
+    fn test_content_override_cleared_by_unchanged_save() {
+        let test = TestServer::start("override-same", "# On disk\n");
+        test.get("/", "");
+
+        test.send("PUT", "/api/content", "text/markdown", "# Unsaved\n");
+        assert!(test.get("/", "").contains("Unsaved</h1>"));
+
+        // The editor's changes were undone and the file saved with the same bytes
+        let mut stream = test.open_event_stream();
+        wait_for_subscribers(&test.server, 1);
+        std::thread::sleep(Duration::from_millis(20));
+        fs::write(&test.file_path, "# On disk\n").unwrap();
+        test.reload_tx.send(()).unwrap();
+        read_until(&mut stream, "On disk</h1>");
+        assert!(test.get("/", "").contains("On disk</h1>"));
+    }
+
+    #[test]
+    fn test_api_only_accepts_local_peers() {
+        let peer = |addr: &str| is_local_peer(Some(&addr.parse().unwrap()));
+        assert!(peer("127.0.0.1:50000"));
+        assert!(peer("[::1]:50000"));
+        assert!(peer("[::ffff:127.0.0.1]:50000"));
+        assert!(!peer("192.168.1.20:50000"));
+        assert!(!peer("[fe80::1]:50000"));
+        assert!(!is_local_peer(None));
+    }
+
+    #[test]
+    fn test_oversized_content_rejected() {
+        let test = TestServer::start("oversized", "# On disk\n");
+        test.get("/", "");
+
+        let mut stream = TcpStream::connect(("127.0.0.1", test.port)).unwrap();
+        write!(
+            stream,
+            "PUT /api/content HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\
+             Content-Type: text/markdown\r\nContent-Length: {}\r\n\r\n# Cut off",
+            MAX_API_BODY + 1
+        )
+        .unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut response = String::new();
+        let _ = stream.read_to_string(&mut response);
+        assert!(response.starts_with("HTTP/1.1 413"), "{}", response);
+        assert!(test.get("/", "").contains("On disk</h1>"));
+    }
+
+    #[test]
+    fn test_older_render_finishing_late_is_dropped() {
+        let test = TestServer::start("late-render", "# On disk\n");
+        let mut stream = test.open_event_stream();
+        wait_for_subscribers(&test.server, 1);
+
+        // A slow render of older content, overtaken by a newer push
+        let older = test.server.start_render();
+        test.server.publish_content("# Newer\n");
+        read_until(&mut stream, "Newer</h1>");
+        assert!(test.server.update_cache("# Older\n", None, older).is_none());
+        assert!(test.get("/", "").contains("Newer</h1>"));
+
+        // The same holds when the newer push matched what was already cached
+        let older = test.server.start_render();
+        test.server.publish_content("# Newer\n");
+        assert!(test.server.update_cache("# Older\n", None, older).is_none());
+        assert!(test.get("/", "").contains("Newer</h1>"));
+    }
+
+    #[test]
+    fn test_content_override_reverted() {
+        let test = TestServer::start("revert", "# On disk\n");
+        test.get("/", "");
+
+        test.send("PUT", "/api/content", "text/plain", "# Unsaved\n");
+        assert!(test.get("/", "").contains("Unsaved</h1>"));
+
+        let response = test.send("DELETE", "/api/content", "text/plain", "");
+        assert!(response.starts_with("HTTP/1.1 204"), "{}", response);
+        assert!(test.get("/", "").contains("On disk</h1>"));
+
+        let response = test.send("POST", "/api/content", "text/plain", "# x");
+        assert!(response.starts_with("HTTP/1.1 405"), "{}", response);
+
+        // Stdin documents have nothing to go back to
+        let stdin = TestServer::start_stdin("revert-stdin", "# Piped");
+        stdin.get("/", "");
+        let response = stdin.send("DELETE", "/api/content", "text/plain", "");
+        assert!(response.starts_with("HTTP/1.1 409"), "{}", response);
+    }
+
+    #[test]
+    fn test_missed_change_detected_by_mtime() {
+        let test = TestServer::start("mtime", "# Before");
+
+        // Change the file without a watcher signal; the request should still see it
+        std::thread::sleep(Duration::from_millis(20));
+        fs::write(&test.file_path, "# Missed").unwrap();
+
+        let response = test.get("/", "");
+        assert!(response.contains("Missed</h1>"));
+    }
+}
@@ -0,0 +1,405 @@
+/// Module for building a static HTML site from a directory of markdown files
+use crate::directory::{is_markdown_file, markdown_files};
+use crate::export::local_file_path;
+use crate::markdown::{extensions, render_document, RenderedDocument};
+use crate::server::page_title;
+use crate::template::{build_standalone_page, escape_html};
+use crate::toc::toc_html;
+use regex::{Captures, Regex};
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+#[derive(Debug)]
+pub enum BuildError {
+    NotADirectory(PathBuf),
+    Io(PathBuf, std::io::Error),
+}
+
+impl std::fmt::Display for BuildError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BuildError::NotADirectory(path) => {
+                write!(f, "'{}' is not a directory", path.display())
+            }
+            BuildError::Io(path, e) => write!(f, "'{}': {}", path.display(), e),
+        }
+    }
+}
+
+impl std::error::Error for BuildError {}
+
+/// A relative link or image that doesn't point at a file in the source directory
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BrokenLink {
+    /// The page containing the link, relative to the source directory
+    pub page: PathBuf,
+    /// The link as written
+    pub target: String,
+    pub reason: &'static str,
+}
+
+impl std::fmt::Display for BrokenLink {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: {} ({})",
+            self.page.display(),
+            self.target,
+            self.reason
+        )
+    }
+}
+
+/// What a build wrote and which links it couldn't resolve
+#[derive(Debug, Default)]
+pub struct BuildReport {
+    pub pages: usize,
+    /// Images and other linked files copied alongside the pages
+    pub files_copied: usize,
+    /// Whether an index page was generated (false if the source has its own `index.md`)
+    pub generated_index: bool,
+    pub broken_links: Vec<BrokenLink>,
+}
+
+/// Where a link in a page points
+enum LinkTarget {
+    /// Another site, an anchor on the same page, or a site-absolute path
+    External,
+    /// A markdown document, which is built to a page of its own
+    Document,
+    /// A directory in the source tree
+    Directory,
+    /// Any other file in the source tree, by canonical path
+    File(PathBuf),
+    Broken(&'static str),
+}
+
+/// Renders every markdown file below `source` to an HTML page below `output`
+///
+/// Each page keeps its relative path with an `.html` extension, and relative links
+/// between documents are rewritten to match. Images and other files the pages link to
+/// are copied to the same relative paths, and every page gets a table of contents and
+/// a link to a generated `index.html` listing all pages. Pages are self-contained, like
+/// `mdview export` output, so the site can be served from anywhere.
+///
+/// # Arguments
+///
+/// * `source` - Directory of markdown files
+/// * `output` - Directory to write the site to; it is created if needed
+///
+/// # Errors
+///
+/// Returns an error if the source isn't a directory or a file can't be read or
+/// written. Broken links don't stop the build; they are listed in the report.
+pub fn build_site(source: &Path, output: &Path) -> Result<BuildReport, BuildError> {
+    if !source.is_dir() {
+        return Err(BuildError::NotADirectory(source.to_path_buf()));
+    }
+    let root = source
+        .canonicalize()
+        .map_err(|e| BuildError::Io(source.to_path_buf(), e))?;
+
+    let pages = markdown_files(&root);
+    let has_index = pages.iter().any(|page| is_index_page(page));
+    let mut report = BuildReport {
+        generated_index: !has_index,
+        ..BuildReport::default()
+    };
+    let mut linked_files = BTreeSet::new();
+    let mut index_entries = Vec::new();
+
+    for page in &pages {
+        let path = root.join(page);
+        let content = fs::read_to_string(&path).map_err(|e| BuildError::Io(path.clone(), e))?;
+        let rendered = render_document(&content);
+        let title = page_title(&rendered, &path);
+        let page_dir = path.parent().unwrap_or(&root);
+
+        let body = link_pattern()
+            .replace_all(&rendered.html, |caps: &Captures| {
+                let url = caps
+                    .get(2)
+                    .or_else(|| caps.get(3))
+                    .map_or("", |m| m.as_str());
+                match resolve_link(url, &root, page_dir) {
+                    LinkTarget::Document => format!("{}\"{}\"", &caps[1], html_link(url)),
+                    LinkTarget::File(file) => {
+                        linked_files.insert(file);
+                        caps[0].to_string()
+                    }
+                    LinkTarget::Broken(reason) => {
+                        report.broken_links.push(BrokenLink {
+                            page: page.clone(),
+                            target: url.replace("&amp;", "&"),
+                            reason,
+                        });
+                        caps[0].to_string()
+                    }
+                    LinkTarget::External | LinkTarget::Directory => caps[0].to_string(),
+                }
+            })
+            .into_owned();
+
+        let html = build_standalone_page(&page_body(page, &rendered, &body), &title);
+        write_file(&output.join(page).with_extension("html"), html.as_bytes())?;
+        report.pages += 1;
+        index_entries.push((page.clone(), index_title(&rendered, page)));
+    }
+
+    for file in linked_files {
+        let Ok(relative) = file.strip_prefix(&root) else {
+            continue;
+        };
+        let data = fs::read(&file).map_err(|e| BuildError::Io(file.clone(), e))?;
+        write_file(&output.join(relative), &data)?;
+        report.files_copied += 1;
+    }
+
+    if !has_index {
+        let title = root
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or("Documents");
+        let html = build_standalone_page(&index_body(title, &index_entries), title);
+        write_file(&output.join("index.html"), html.as_bytes())?;
+    }
+
+    Ok(report)
+}
+
+/// Matches link `href`s and image `src`s, single- or double-quoted
+fn link_pattern() -> &'static Regex {
+    static PATTERN: OnceLock<Regex> = OnceLock::new();
+    PATTERN.get_or_init(|| {
+        Regex::new(r#"(<(?:a|img)\b[^>]*?\b(?:href|src)=)(?:"([^"]*)"|'([^']*)')"#)
+            .expect("Valid link pattern")
+    })
+}
+
+/// Works out what a link on a page in `page_dir` points at
+fn resolve_link(url: &str, root: &Path, page_dir: &Path) -> LinkTarget {
+    if url.starts_with('/') {
+        return LinkTarget::External;
+    }
+    let Some(path) = local_file_path(url, page_dir) else {
+        return LinkTarget::External;
+    };
+    if path == page_dir {
+        // Only a query string, which refers to the page itself
+        return LinkTarget::External;
+    }
+
+    let Ok(path) = path.canonicalize() else {
+        return LinkTarget::Broken("not found");
+    };
+    if !path.starts_with(root) {
+        LinkTarget::Broken("outside the source directory")
+    } else if path.is_dir() {
+        LinkTarget::Directory
+    } else if is_markdown_file(&path) {
+        LinkTarget::Document
+    } else {
+        LinkTarget::File(path)
+    }
+}
+
+/// Swaps the extension of a link to a markdown document for `.html`, keeping any
+/// query or fragment
+fn html_link(url: &str) -> String {
+    let path_end = url.find(['?', '#']).unwrap_or(url.len());
+    let (path, suffix) = url.split_at(path_end);
+    match path.rfind('.') {
+        Some(dot) => format!("{}.html{}", &path[..dot], suffix),
+        None => url.to_string(),
+    }
+}
+
+/// Whether a page is built to the site's `index.html`
+fn is_index_page(page: &Path) -> bool {
+    page.with_extension("html") == Path::new("index.html")
+}
+
+/// Adds the link back to the index and the table of contents to a page
+fn page_body(page: &Path, rendered: &RenderedDocument, body: &str) -> String {
+    let mut html = String::new();
+    if !is_index_page(page) {
+        let depth = page.components().count() - 1;
+        html.push_str(&format!(
+            "<p class=\"site-nav\"><a href=\"{}index.html\">Index</a></p>\n",
+            "../".repeat(depth)
+        ));
+    }
+    // A lone heading isn't worth a table of contents, and without ids it can't link
+    if rendered.headings.len() > 1 && extensions().header_ids {
+        html.push_str(&format!(
+            "<nav class=\"page-toc\">\n<details open>\n<summary>Contents</summary>\n{}</details>\n</nav>\n",
+            toc_html(&rendered.headings)
+        ));
+    }
+    html.push_str(body);
+    html
+}
+
+/// The name a page is listed under in the index: its title, or else its first
+/// top-level heading, or else its path
+fn index_title(rendered: &RenderedDocument, page: &Path) -> String {
+    rendered
+        .title
+        .clone()
+        .or_else(|| {
+            rendered
+                .headings
+                .iter()
+                .find(|heading| heading.level == 1)
+                .map(|heading| heading.text.clone())
+        })
+        .unwrap_or_else(|| page.to_string_lossy().replace('\\', "/"))
+}
+
+/// Renders the generated index page listing every page
+fn index_body(title: &str, entries: &[(PathBuf, String)]) -> String {
+    let mut html = format!("<h1>{}</h1>\n", escape_html(title));
+    if entries.is_empty() {
+        html.push_str("<p>No markdown files found.</p>\n");
+        return html;
+    }
+
+    html.push_str("<ul class=\"site-index\">\n");
+    for (page, name) in entries {
+        let path = page.to_string_lossy().replace('\\', "/");
+        html.push_str(&format!(
+            "<li><a href=\"{}\">{}</a> <code>{}</code></li>\n",
+            escape_html(&html_link(&path)),
+            escape_html(name),
+            escape_html(&path)
+        ));
+    }
+    html.push_str("</ul>\n");
+    html
+}
+
+/// Writes a file, creating its parent directories first
+fn write_file(path: &Path, data: &[u8]) -> Result<(), BuildError> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| BuildError::Io(parent.to_path_buf(), e))?;
+    }
+    fs::write(path, data).map_err(|e| BuildError::Io(path.to_path_buf(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Creates a scratch docs tree and an empty output directory next to it
+    fn scratch_site(name: &str) -> (PathBuf, PathBuf) {
+        let base =
+            std::env::temp_dir().join(format!("mdview-site-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&base);
+        let docs = base.join("docs");
+        fs::create_dir_all(docs.join("guide")).unwrap();
+        fs::create_dir_all(docs.join("img")).unwrap();
+        fs::write(
+            docs.join("README.md"),
+            "# Project\n\nSee [setup](guide/setup.md#install \"Setup\") and [the API](api.md).\n\n\
+             ![Logo](img/logo.png)\n\n## Usage\n\n[Remote](https://example.com/x.md), [top](#usage)\n",
+        )
+        .unwrap();
+        fs::write(
+            docs.join("guide/setup.md"),
+            "# Setup\n\n## Install\n\nBack to [the readme](../README.md).\n\n![Missing](../img/missing.png)\n",
+        )
+        .unwrap();
+        fs::write(docs.join("img/logo.png"), b"png").unwrap();
+        fs::write(docs.join("img/unused.png"), b"png").unwrap();
+        (docs, base.join("site"))
+    }
+
+    #[test]
+    fn test_html_link() {
+        assert_eq!(html_link("guide/setup.md"), "guide/setup.html");
+        assert_eq!(html_link("../README.md#usage"), "../README.html#usage");
+        assert_eq!(html_link("notes.markdown?raw=1"), "notes.html?raw=1");
+    }
+
+    #[test]
+    fn test_build_site() {
+        let (docs, site) = scratch_site("build");
+        let report = build_site(&docs, &site).unwrap();
+
+        assert_eq!(report.pages, 2);
+        assert_eq!(report.files_copied, 1);
+        assert!(report.generated_index);
+        assert!(site.join("img/logo.png").exists());
+        assert!(!site.join("img/unused.png").exists());
+
+        let readme = fs::read_to_string(site.join("README.html")).unwrap();
+        assert!(readme.contains("<a href=\"guide/setup.html#install\" title=\"Setup\">setup</a>"));
+        assert!(readme.contains("<a href=\"https://example.com/x.md\">"));
+        assert!(readme.contains("<a href=\"#usage\">top</a>"));
+        assert!(readme.contains("<img src=\"img/logo.png\""));
+        assert!(readme.contains("<nav class=\"page-toc\">"));
+        assert!(readme.contains("<a href=\"#usage\">Usage</a>"));
+        assert!(readme.contains("<a href=\"index.html\">Index</a>"));
+        assert!(!readme.contains("EventSource"));
+
+        let setup = fs::read_to_string(site.join("guide/setup.html")).unwrap();
+        assert!(setup.contains("<a href=\"../README.html\">the readme</a>"));
+        assert!(setup.contains("<a href=\"../index.html\">Index</a>"));
+
+        let index = fs::read_to_string(site.join("index.html")).unwrap();
+        assert!(
+            index.contains("<li><a href=\"README.html\">Project</a> <code>README.md</code></li>")
+        );
+        assert!(index.contains("<li><a href=\"guide/setup.html\">Setup</a>"));
+
+        assert_eq!(
+            report.broken_links,
+            [
+                BrokenLink {
+                    page: PathBuf::from("README.md"),
+                    target: "api.md".to_string(),
+                    reason: "not found",
+                },
+                BrokenLink {
+                    page: PathBuf::from("guide/setup.md"),
+                    target: "../img/missing.png".to_string(),
+                    reason: "not found",
+                },
+            ]
+        );
+
+        let _ = fs::remove_dir_all(docs.parent().unwrap());
+    }
+
+    #[test]
+    fn test_own_index_and_links_outside_source() {
+        let (docs, site) = scratch_site("index");
+        fs::write(
+            docs.join("index.md"),
+            "# Home\n\n[Outside](../elsewhere.md)\n",
+        )
+        .unwrap();
+        fs::write(docs.parent().unwrap().join("elsewhere.md"), "# Elsewhere\n").unwrap();
+
+        let report = build_site(&docs, &site).unwrap();
+        assert!(!report.generated_index);
+        let index = fs::read_to_string(site.join("index.html")).unwrap();
+        assert!(index.contains("<h1>"));
+        assert!(!index.contains("<p class=\"site-nav\">"));
+        assert!(report.broken_links.contains(&BrokenLink {
+            page: PathBuf::from("index.md"),
+            target: "../elsewhere.md".to_string(),
+            reason: "outside the source directory",
+        }));
+
+        let _ = fs::remove_dir_all(docs.parent().unwrap());
+    }
+
+    #[test]
+    fn test_source_must_be_a_directory() {
+        let err = build_site(Path::new("Cargo.toml"), Path::new("site")).unwrap_err();
+        assert!(matches!(err, BuildError::NotADirectory(_)));
+    }
+}
@@ -7,8 +7,14 @@ use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fs::{self, File, OpenOptions};
 use std::io::{Read, Write};
+use std::os::unix::ffi::OsStrExt;
 use std::path::{Path, PathBuf};
 
+/// Current registry format version
+///
+/// Version 1 tracked one daemon process per file; those files are discarded on load.
+const STATE_VERSION: u32 = 2;
+
 #[derive(Debug)]
 pub enum StateError {
     NoProjectDirs,
@@ -42,27 +48,42 @@ impl From<serde_json::Error> for StateError {
     }
 }
 
+/// The running supervisor process
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Instance {
+pub struct SupervisorInfo {
     pub pid: i32,
     pub port: u16,
-    pub file_path: PathBuf,
     pub started_at: DateTime<Utc>,
     pub log_file: PathBuf,
 }
 
+/// A document registered with the supervisor, served at `/doc/<id>/`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Document {
+    pub id: String,
+    pub file_path: PathBuf,
+    pub started_at: DateTime<Utc>,
+}
+
+/// The supervisor's persisted registry
+///
+/// Documents survive a supervisor restart: they are restored the next time the
+/// supervisor starts.
 #[derive(Debug, Serialize, Deserialize)]
 pub struct StateFile {
     pub version: u32,
     #[serde(default)]
-    pub instances: HashMap<PathBuf, Instance>,
+    pub supervisor: Option<SupervisorInfo>,
+    #[serde(default)]
+    pub documents: HashMap<PathBuf, Document>,
 }
 
 impl Default for StateFile {
     fn default() -> Self {
         Self {
-            version: 1,
-            instances: HashMap::new(),
+            version: STATE_VERSION,
+            supervisor: None,
+            documents: HashMap::new(),
         }
     }
 }
@@ -87,6 +108,18 @@ impl StateFile {
         Ok(data_dir.join("instances.json"))
     }
 
+    /// Get the supervisor's control socket path
+    pub fn get_socket_path() -> Result<PathBuf, StateError> {
+        let data_dir = Self::get_data_dir()?;
+        Ok(data_dir.join("mdview.sock"))
+    }
+
+    /// Get the lock file held by the running supervisor
+    pub fn get_lock_path() -> Result<PathBuf, StateError> {
+        let data_dir = Self::get_data_dir()?;
+        Ok(data_dir.join("supervisor.lock"))
+    }
+
     /// Load the state file, creating directories if needed
     pub fn load() -> Result<Self, StateError> {
         let state_path = Self::get_state_file_path()?;
@@ -116,7 +149,8 @@ impl StateFile {
         FileExt::unlock(&file).map_err(|_| StateError::LockFailed)?;
 
         // Parse JSON, falling back to default if corrupted
-        match serde_json::from_str(&contents) {
+        match serde_json::from_str::<Self>(&contents) {
+            Ok(state) if state.version < STATE_VERSION => Ok(Self::default()),
             Ok(state) => Ok(state),
             Err(_) => {
                 // Backup corrupted file
@@ -159,19 +193,19 @@ impl StateFile {
         Ok(())
     }
 
-    /// Add an instance to the state
-    pub fn add_instance(&mut self, instance: Instance) {
-        self.instances.insert(instance.file_path.clone(), instance);
+    /// Add a document to the registry
+    pub fn add_document(&mut self, document: Document) {
+        self.documents.insert(document.file_path.clone(), document);
     }
 
-    /// Remove an instance by file path
-    pub fn remove_instance(&mut self, file_path: &Path) -> Option<Instance> {
-        self.instances.remove(file_path)
+    /// Remove a document by file path
+    pub fn remove_document(&mut self, file_path: &Path) -> Option<Document> {
+        self.documents.remove(file_path)
     }
 
-    /// Get an instance by file path
-    pub fn get_instance(&self, file_path: &Path) -> Option<&Instance> {
-        self.instances.get(file_path)
+    /// Get a document by file path
+    pub fn get_document(&self, file_path: &Path) -> Option<&Document> {
+        self.documents.get(file_path)
     }
 
     /// Check if a process is still running
@@ -184,59 +218,63 @@ impl StateFile {
         }
     }
 
-    /// Clean up stale instances (processes that are no longer running)
-    /// Returns the list of removed stale instances
-    pub fn cleanup_stale(&mut self) -> Vec<Instance> {
-        let stale_paths: Vec<PathBuf> = self
-            .instances
-            .iter()
-            .filter(|(_, inst)| !Self::is_process_running(inst.pid))
-            .map(|(path, _)| path.clone())
-            .collect();
-
-        let mut removed = Vec::new();
-        for path in stale_paths {
-            if let Some(inst) = self.instances.remove(&path) {
-                removed.push(inst);
-            }
+    /// Forget the supervisor if its process is no longer running
+    /// Returns the stale supervisor entry, if one was removed
+    pub fn cleanup_stale(&mut self) -> Option<SupervisorInfo> {
+        match &self.supervisor {
+            Some(info) if !Self::is_process_running(info.pid) => self.supervisor.take(),
+            _ => None,
         }
-        removed
     }
 
-    /// Get all instances
-    pub fn all_instances(&self) -> impl Iterator<Item = &Instance> {
-        self.instances.values()
+    /// Get all documents
+    pub fn all_documents(&self) -> impl Iterator<Item = &Document> {
+        self.documents.values()
     }
 }
 
-/// Generate a sanitized log filename from the markdown file path
-pub fn generate_log_filename(file_path: &Path, port: u16) -> String {
+/// Generate a URL-safe document id from the markdown file path
+///
+/// The id is the sanitized file stem followed by a short hash of the full path, so it is
+/// readable, stable across restarts, and distinct for same-named files in different
+/// directories.
+pub fn generate_document_id(file_path: &Path) -> String {
     let stem = file_path
         .file_stem()
         .and_then(|s| s.to_str())
-        .unwrap_or("unknown");
+        .unwrap_or("doc");
 
-    // Sanitize: keep only alphanumeric, dash, underscore
+    // Sanitize: keep only lowercase alphanumeric and dash
     let sanitized: String = stem
         .chars()
         .map(|c| {
-            if c.is_alphanumeric() || c == '-' || c == '_' {
-                c
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_lowercase()
             } else {
-                '_'
+                '-'
             }
         })
         .take(50) // Limit length
         .collect();
 
-    format!("{}-{}.log", sanitized, port)
+    let hash = fnv1a(file_path.as_os_str().as_bytes());
+    format!("{}-{:06x}", sanitized, hash & 0xff_ffff)
+}
+
+/// 64-bit FNV-1a hash
+///
+/// Unlike `DefaultHasher`, its output is fixed, so ids survive upgrading mdview or
+/// the Rust toolchain.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
 }
 
-/// Get the log file path for a given markdown file
-pub fn get_log_path(file_path: &Path, port: u16) -> Result<PathBuf, StateError> {
+/// Get the supervisor's log file path
+pub fn get_log_path() -> Result<PathBuf, StateError> {
     let logs_dir = StateFile::get_logs_dir()?;
-    let filename = generate_log_filename(file_path, port);
-    Ok(logs_dir.join(filename))
+    Ok(logs_dir.join("supervisor.log"))
 }
 
 #[cfg(test)]
@@ -244,24 +282,50 @@ mod tests {
     use super::*;
 
     #[test]
-    fn test_generate_log_filename() {
+    fn test_generate_document_id() {
         let path = PathBuf::from("/some/path/README.md");
-        let filename = generate_log_filename(&path, 6914);
-        assert_eq!(filename, "README-6914.log");
+        let id = generate_document_id(&path);
+        assert!(id.starts_with("readme-"), "unexpected id {}", id);
+        assert_eq!(id.len(), "readme-".len() + 6);
+        assert_eq!(id, generate_document_id(&path));
+    }
+
+    #[test]
+    fn test_document_id_hash_is_fixed() {
+        // Published FNV-1a test vectors
+        assert_eq!(fnv1a(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(fnv1a(b"a"), 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(fnv1a(b"foobar"), 0x8594_4171_f739_67e8);
     }
 
     #[test]
-    fn test_generate_log_filename_special_chars() {
+    fn test_generate_document_id_special_chars() {
         let path = PathBuf::from("/some/path/my file (1).md");
-        let filename = generate_log_filename(&path, 6915);
-        assert_eq!(filename, "my_file__1_-6915.log");
+        let id = generate_document_id(&path);
+        assert!(id.starts_with("my-file--1--"), "unexpected id {}", id);
+    }
+
+    #[test]
+    fn test_generate_document_id_distinguishes_directories() {
+        let a = generate_document_id(&PathBuf::from("/a/README.md"));
+        let b = generate_document_id(&PathBuf::from("/b/README.md"));
+        assert_ne!(a, b);
     }
 
     #[test]
     fn test_state_file_default() {
         let state = StateFile::default();
-        assert_eq!(state.version, 1);
-        assert!(state.instances.is_empty());
+        assert_eq!(state.version, 2);
+        assert!(state.supervisor.is_none());
+        assert!(state.documents.is_empty());
+    }
+
+    #[test]
+    fn test_state_file_ignores_version_one_instances() {
+        let json = r#"{"version": 1, "instances": {"/a.md": {"pid": 1, "port": 6914}}}"#;
+        let state: StateFile = serde_json::from_str(json).unwrap();
+        assert!(state.documents.is_empty());
+        assert!(state.supervisor.is_none());
     }
 
     #[test]
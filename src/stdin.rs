@@ -0,0 +1,120 @@
+/// Module for reading markdown documents from standard input
+use std::io::{self, ErrorKind, Read};
+
+/// Bytes that end one document and start the next in follow mode: NUL and form feed
+pub const DOCUMENT_DELIMITERS: &[u8] = b"\0\x0c";
+
+/// Reads the whole of a stream as a single document
+///
+/// # Errors
+///
+/// Returns an error if the stream can't be read
+pub fn read_document<R: Read>(mut reader: R) -> io::Result<String> {
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// Reads a stream of documents separated by `DOCUMENT_DELIMITERS`
+///
+/// `on_document` is called as soon as each delimiter arrives, so a long-running
+/// producer can push a new version of the document at any time. Whatever follows the
+/// last delimiter is passed on at end of input. Documents that are empty or only
+/// whitespace are skipped, so `\0\n` and trailing delimiters don't blank the page.
+///
+/// # Arguments
+///
+/// * `reader` - The stream to read, usually stdin
+/// * `on_document` - Called with each complete document
+///
+/// # Errors
+///
+/// Returns an error if the stream can't be read
+pub fn read_documents<R: Read>(
+    mut reader: R,
+    mut on_document: impl FnMut(String),
+) -> io::Result<()> {
+    let mut document = Vec::new();
+    let mut buf = [0u8; 8192];
+    let mut emit = |document: &mut Vec<u8>| {
+        let text = String::from_utf8_lossy(document).into_owned();
+        document.clear();
+        if !text.trim().is_empty() {
+            on_document(text);
+        }
+    };
+
+    loop {
+        let read = match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        for &byte in &buf[..read] {
+            if DOCUMENT_DELIMITERS.contains(&byte) {
+                emit(&mut document);
+            } else {
+                document.push(byte);
+            }
+        }
+    }
+    emit(&mut document);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn split(input: &[u8]) -> Vec<String> {
+        let mut documents = Vec::new();
+        read_documents(Cursor::new(input), |doc| documents.push(doc)).unwrap();
+        documents
+    }
+
+    #[test]
+    fn test_read_document_keeps_delimiters() {
+        let doc = read_document(Cursor::new(b"# One\x0c# Two\n")).unwrap();
+        assert_eq!(doc, "# One\x0c# Two\n");
+    }
+
+    #[test]
+    fn test_documents_split_on_nul_and_form_feed() {
+        assert_eq!(
+            split(b"# One\n\0# Two\n\x0c\n# Three"),
+            ["# One\n", "# Two\n", "\n# Three"]
+        );
+    }
+
+    #[test]
+    fn test_blank_documents_are_skipped() {
+        assert_eq!(split(b"\0\0# Only\n\0\n\x0c"), ["# Only\n"]);
+        assert!(split(b"").is_empty());
+    }
+
+    #[test]
+    fn test_documents_arrive_before_end_of_input() {
+        // A reader that delivers one document per read, then blocks forever
+        struct Chunks(Vec<&'static [u8]>);
+        impl Read for Chunks {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                match self.0.pop() {
+                    Some(chunk) => {
+                        buf[..chunk.len()].copy_from_slice(chunk);
+                        Ok(chunk.len())
+                    }
+                    None => Err(io::Error::other("still open")),
+                }
+            }
+        }
+
+        let mut documents = Vec::new();
+        let result = read_documents(Chunks(vec![b"# Two\0", b"# One\0"]), |doc| {
+            documents.push(doc)
+        });
+        assert!(result.is_err());
+        assert_eq!(documents, ["# One", "# Two"]);
+    }
+}
@@ -0,0 +1,955 @@
+/// Module for the background supervisor that serves many documents on one port
+use crate::assets::{is_asset_request, serve_asset};
+use crate::config::{EditorConfig, ServerConfig, WatchConfig};
+use crate::daemon::{daemonize, get_pid, DaemonError, DaemonizeResult};
+use crate::server::{
+    base_url, find_available_port, is_event_stream_request, respond_not_found, split_query,
+    stream_events, Broadcaster, ServerEvent, WatchedDocument,
+};
+use crate::state::{
+    generate_document_id, get_log_path, Document, StateError, StateFile, SupervisorInfo,
+};
+use crate::template::{build_html_page, escape_html};
+use chrono::{DateTime, Utc};
+use fs2::FileExt;
+use serde::{Deserialize, Serialize};
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook::flag;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tiny_http::{Header, Request, Response, Server};
+
+/// How long the CLI waits for a freshly spawned supervisor to accept connections
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug)]
+pub enum SupervisorError {
+    NotRunning,
+    AlreadyRunning,
+    NoPort,
+    StartTimeout(PathBuf),
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    State(StateError),
+    Daemon(DaemonError),
+    Http(String),
+}
+
+impl std::fmt::Display for SupervisorError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SupervisorError::NotRunning => write!(f, "Supervisor is not running"),
+            SupervisorError::AlreadyRunning => write!(f, "Another supervisor is already running"),
+            SupervisorError::NoPort => write!(f, "Could not find an available port"),
+            SupervisorError::StartTimeout(log) => write!(
+                f,
+                "Timed out waiting for the supervisor to start (see {})",
+                log.display()
+            ),
+            SupervisorError::Io(e) => write!(f, "IO error: {}", e),
+            SupervisorError::Json(e) => write!(f, "JSON error: {}", e),
+            SupervisorError::State(e) => write!(f, "State error: {}", e),
+            SupervisorError::Daemon(e) => write!(f, "Daemon error: {}", e),
+            SupervisorError::Http(e) => write!(f, "HTTP server error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for SupervisorError {}
+
+impl From<std::io::Error> for SupervisorError {
+    fn from(e: std::io::Error) -> Self {
+        SupervisorError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for SupervisorError {
+    fn from(e: serde_json::Error) -> Self {
+        SupervisorError::Json(e)
+    }
+}
+
+impl From<StateError> for SupervisorError {
+    fn from(e: StateError) -> Self {
+        SupervisorError::State(e)
+    }
+}
+
+impl From<DaemonError> for SupervisorError {
+    fn from(e: DaemonError) -> Self {
+        SupervisorError::Daemon(e)
+    }
+}
+
+/// A request sent by the CLI over the control socket, one JSON object per line
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "lowercase")]
+pub enum ControlRequest {
+    /// Start serving a file (only updating its editor if it is already served)
+    Serve {
+        file: PathBuf,
+        /// How the document's page opens the file in an editor
+        #[serde(default)]
+        editor: EditorConfig,
+        /// How the document's file is watched, if it isn't served yet
+        #[serde(default)]
+        watch: WatchConfig,
+    },
+    /// Stop serving a file
+    Stop { file: PathBuf },
+    /// Describe every served document
+    List,
+}
+
+/// The supervisor's reply to a `ControlRequest`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "lowercase")]
+pub enum ControlResponse {
+    Serving {
+        url: String,
+        already_serving: bool,
+    },
+    /// The document was removed; `exiting` is set when it was the last one
+    Stopped {
+        exiting: bool,
+    },
+    Documents {
+        pid: i32,
+        port: u16,
+        documents: Vec<DocumentInfo>,
+    },
+    Error {
+        message: String,
+    },
+}
+
+/// A served document as reported by `mdview list`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DocumentInfo {
+    pub id: String,
+    pub file_path: PathBuf,
+    pub url: String,
+    pub started_at: DateTime<Utc>,
+}
+
+/// A document currently being served
+struct HostedDocument {
+    file_path: PathBuf,
+    started_at: DateTime<Utc>,
+    document: WatchedDocument,
+}
+
+/// Returns the id a file is served under, if it is served
+fn find_document(documents: &HashMap<String, HostedDocument>, file_path: &Path) -> Option<String> {
+    documents
+        .iter()
+        .find(|(_, hosted)| hosted.file_path == file_path)
+        .map(|(id, _)| id.clone())
+}
+
+/// Picks the id a newly served file is mounted under
+///
+/// This is `generate_document_id` unless another file already holds that id, in which
+/// case a number is appended so the two never share a URL.
+fn free_document_id(documents: &HashMap<String, HostedDocument>, file_path: &Path) -> String {
+    let base = generate_document_id(file_path);
+    let mut id = base.clone();
+    let mut n = 1;
+    while documents.contains_key(&id) {
+        n += 1;
+        id = format!("{}-{}", base, n);
+    }
+    id
+}
+
+/// Hosts every served document under `/doc/<id>/` on a single port
+pub struct Supervisor {
+    bind: String,
+    port: u16,
+    /// Whether the registry in `StateFile` should track this supervisor's documents
+    persist: bool,
+    documents: Mutex<HashMap<String, HostedDocument>>,
+    /// Live updates for the index page at `/`
+    index_hub: Broadcaster<ServerEvent>,
+}
+
+impl Supervisor {
+    /// Creates a supervisor with no documents
+    ///
+    /// # Arguments
+    ///
+    /// * `bind` - The address the HTTP server is (or will be) bound to
+    /// * `port` - The port the HTTP server is (or will be) bound to, used to build URLs
+    /// * `persist` - Whether to record served documents in the on-disk registry
+    pub fn new(bind: &str, port: u16, persist: bool) -> Self {
+        Self {
+            bind: bind.to_string(),
+            port,
+            persist,
+            documents: Mutex::new(HashMap::new()),
+            index_hub: Broadcaster::new(),
+        }
+    }
+
+    /// Returns the URL a document id is served at
+    fn url_for(&self, id: &str) -> String {
+        format!("{}/doc/{}/", base_url(&self.bind, self.port), id)
+    }
+
+    /// Answers a control request
+    pub fn handle_control(&self, request: ControlRequest) -> ControlResponse {
+        match request {
+            ControlRequest::Serve {
+                file,
+                editor,
+                watch,
+            } => self.serve_document(&file, &editor, &watch, Utc::now()),
+            ControlRequest::Stop { file } => self.stop_document(&file),
+            ControlRequest::List => self.list_documents(),
+        }
+    }
+
+    /// Starts serving a file, or reports where it is already served
+    ///
+    /// The editor settings replace those the document was served with before, so a
+    /// changed config or `--editor` flag takes effect without restarting the supervisor.
+    /// The watch settings only apply when the document is opened; its watcher keeps
+    /// running as it is.
+    fn serve_document(
+        &self,
+        file_path: &Path,
+        editor: &EditorConfig,
+        watch: &WatchConfig,
+        started_at: DateTime<Utc>,
+    ) -> ControlResponse {
+        if let Some(response) = self.serving_already(file_path, editor) {
+            return response;
+        }
+
+        // Rendering can wait on diagram tools, so it happens without holding the lock
+        let document = match WatchedDocument::open(file_path, watch.debounce()) {
+            Ok(document) => document,
+            Err(message) => return ControlResponse::Error { message },
+        };
+        document.server.set_editor(editor.clone());
+
+        let id = {
+            let mut documents = self.documents.lock().unwrap();
+            // Another request may have opened the file in the meantime
+            if let Some(id) = find_document(&documents, file_path) {
+                documents[&id].document.server.set_editor(editor.clone());
+                return ControlResponse::Serving {
+                    url: self.url_for(&id),
+                    already_serving: true,
+                };
+            }
+
+            let id = free_document_id(&documents, file_path);
+            documents.insert(
+                id.clone(),
+                HostedDocument {
+                    file_path: file_path.to_path_buf(),
+                    started_at,
+                    document,
+                },
+            );
+            id
+        };
+
+        println!(
+            "[{}] Serving '{}' as /doc/{}/",
+            Utc::now().format("%Y-%m-%d %H:%M:%S"),
+            file_path.display(),
+            id
+        );
+        self.documents_changed();
+
+        ControlResponse::Serving {
+            url: self.url_for(&id),
+            already_serving: false,
+        }
+    }
+
+    /// Updates the editor of a file that is already served and reports where it is
+    ///
+    /// # Returns
+    ///
+    /// None if the file isn't served yet
+    fn serving_already(&self, file_path: &Path, editor: &EditorConfig) -> Option<ControlResponse> {
+        let documents = self.documents.lock().unwrap();
+        let id = find_document(&documents, file_path)?;
+        documents[&id].document.server.set_editor(editor.clone());
+        Some(ControlResponse::Serving {
+            url: self.url_for(&id),
+            already_serving: true,
+        })
+    }
+
+    /// Stops serving a file
+    fn stop_document(&self, file_path: &Path) -> ControlResponse {
+        let exiting = {
+            let mut documents = self.documents.lock().unwrap();
+            let Some(id) = find_document(&documents, file_path) else {
+                return ControlResponse::Error {
+                    message: format!("No running instance found for '{}'", file_path.display()),
+                };
+            };
+            documents.remove(&id);
+            documents.is_empty()
+        };
+
+        println!(
+            "[{}] Stopped serving '{}'",
+            Utc::now().format("%Y-%m-%d %H:%M:%S"),
+            file_path.display()
+        );
+        self.documents_changed();
+
+        ControlResponse::Stopped { exiting }
+    }
+
+    /// Describes every served document
+    fn list_documents(&self) -> ControlResponse {
+        let documents = self.documents.lock().unwrap();
+        let mut infos: Vec<DocumentInfo> = documents
+            .iter()
+            .map(|(id, doc)| DocumentInfo {
+                id: id.clone(),
+                file_path: doc.file_path.clone(),
+                url: self.url_for(id),
+                started_at: doc.started_at,
+            })
+            .collect();
+        infos.sort_by(|a, b| a.file_path.cmp(&b.file_path));
+
+        ControlResponse::Documents {
+            pid: get_pid(),
+            port: self.port,
+            documents: infos,
+        }
+    }
+
+    /// Persists the registry and refreshes the index page after documents change
+    fn documents_changed(&self) {
+        if self.persist {
+            if let Err(e) = self.save_registry() {
+                eprintln!("Warning: Could not save state: {}", e);
+            }
+        }
+        self.index_hub.publish(ServerEvent::Update {
+            html: self.index_body(),
+            title: None,
+            toc: None,
+        });
+    }
+
+    /// Writes the current documents to the registry
+    fn save_registry(&self) -> Result<(), StateError> {
+        let mut state = StateFile::load()?;
+        state.documents.clear();
+        for (id, doc) in self.documents.lock().unwrap().iter() {
+            state.add_document(Document {
+                id: id.clone(),
+                file_path: doc.file_path.clone(),
+                started_at: doc.started_at,
+            });
+        }
+        state.save()
+    }
+
+    /// Serves every document recorded in the registry by a previous supervisor, with
+    /// the editor and watch settings of the command that started this one
+    fn restore_documents(&self, state: &StateFile, editor: &EditorConfig, watch: &WatchConfig) {
+        for document in state.all_documents() {
+            if !document.file_path.is_file() {
+                eprintln!(
+                    "Skipping '{}': file no longer exists",
+                    document.file_path.display()
+                );
+                continue;
+            }
+            if let ControlResponse::Error { message } =
+                self.serve_document(&document.file_path, editor, watch, document.started_at)
+            {
+                eprintln!(
+                    "Could not restore '{}': {}",
+                    document.file_path.display(),
+                    message
+                );
+            }
+        }
+    }
+
+    /// Renders the list of served documents for the index page
+    fn index_body(&self) -> String {
+        let documents = self.documents.lock().unwrap();
+        if documents.is_empty() {
+            return "<h1>mdview</h1>\n<p>No documents are being served.</p>".to_string();
+        }
+
+        let mut entries: Vec<(&String, &HostedDocument)> = documents.iter().collect();
+        entries.sort_by(|a, b| a.1.file_path.cmp(&b.1.file_path));
+
+        let mut html = String::from("<h1>mdview</h1>\n<ul>\n");
+        for (id, doc) in entries {
+            let name = doc
+                .file_path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .unwrap_or("Markdown");
+            html.push_str(&format!(
+                "<li><a href=\"/doc/{}/\">{}</a> <code>{}</code></li>\n",
+                id,
+                escape_html(name),
+                escape_html(&doc.file_path.display().to_string())
+            ));
+        }
+        html.push_str("</ul>");
+        html
+    }
+
+    /// Handles an HTTP request, dispatching `/doc/<id>/...` to that document's server
+    pub fn handle_request(&self, request: Request) {
+        let url = request.url().to_string();
+        let (path, _) = split_query(&url);
+
+        if is_asset_request(path) {
+            serve_asset(request, path);
+            return;
+        }
+
+        if path == "/" && is_event_stream_request(&url) {
+            stream_events(request, &self.index_hub);
+            return;
+        }
+
+        if path == "/" {
+            let page = build_html_page(&self.index_body(), "mdview");
+            let response = Response::from_string(page).with_header(
+                Header::from_bytes(&b"Content-Type"[..], &b"text/html; charset=utf-8"[..]).unwrap(),
+            );
+            let _ = request.respond(response);
+            return;
+        }
+
+        let rest = match url.strip_prefix("/doc/") {
+            Some(rest) => rest,
+            None => return respond_not_found(request),
+        };
+        let (id, path) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, ""),
+        };
+
+        // Release the lock before handling; SSE requests block for the connection's lifetime
+        let server = self
+            .documents
+            .lock()
+            .unwrap()
+            .get(id)
+            .map(|doc| Arc::clone(&doc.document.server));
+
+        match server {
+            None => respond_not_found(request),
+            Some(_) if path.is_empty() => {
+                // Relative URLs in the page (events, images) need the trailing slash
+                let location = format!("/doc/{}/", id);
+                let response = Response::empty(301).with_header(
+                    Header::from_bytes(&b"Location"[..], location.as_bytes()).unwrap(),
+                );
+                let _ = request.respond(response);
+            }
+            Some(server) => server.handle_path(request, path),
+        }
+    }
+
+    /// Runs the HTTP server, blocking indefinitely
+    pub fn run_http(self: &Arc<Self>) -> Result<(), SupervisorError> {
+        let http_server = Server::http((self.bind.as_str(), self.port))
+            .map_err(|e| SupervisorError::Http(e.to_string()))?;
+
+        for request in http_server.incoming_requests() {
+            let supervisor = Arc::clone(self);
+
+            // Spawn a thread for each request
+            std::thread::spawn(move || {
+                supervisor.handle_request(request);
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Reads one control request from the stream and writes back the response
+    ///
+    /// # Returns
+    ///
+    /// True if the supervisor should exit because its last document was stopped
+    pub fn handle_control_connection(&self, stream: UnixStream) -> bool {
+        let mut line = String::new();
+        if BufReader::new(&stream).read_line(&mut line).is_err() {
+            return false;
+        }
+
+        let response = match serde_json::from_str::<ControlRequest>(&line) {
+            Ok(request) => self.handle_control(request),
+            Err(e) => ControlResponse::Error {
+                message: format!("Invalid request: {}", e),
+            },
+        };
+
+        let exiting = matches!(response, ControlResponse::Stopped { exiting: true });
+        if let Ok(json) = serde_json::to_string(&response) {
+            let mut writer = &stream;
+            let _ = writeln!(writer, "{}", json);
+            let _ = writer.flush();
+        }
+        exiting
+    }
+}
+
+/// Sends a request to the running supervisor over its control socket
+///
+/// # Errors
+///
+/// Returns `SupervisorError::NotRunning` if no supervisor is listening
+pub fn send_request(request: &ControlRequest) -> Result<ControlResponse, SupervisorError> {
+    let socket_path = StateFile::get_socket_path()?;
+    let stream = match UnixStream::connect(&socket_path) {
+        Ok(stream) => stream,
+        Err(e) if matches!(e.kind(), ErrorKind::NotFound | ErrorKind::ConnectionRefused) => {
+            return Err(SupervisorError::NotRunning)
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut writer = &stream;
+    writeln!(writer, "{}", serde_json::to_string(request)?)?;
+    writer.flush()?;
+
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line)?;
+    Ok(serde_json::from_str(&line)?)
+}
+
+/// Makes sure a supervisor is running, starting one in the background if needed
+///
+/// # Arguments
+///
+/// * `server` - Address and port range a newly started supervisor listens on
+/// * `editor` - Editor settings for documents a new supervisor restores from the registry
+/// * `watch` - Watch settings for documents a new supervisor restores from the registry
+///
+/// # Returns
+///
+/// True if a new supervisor was started
+pub fn ensure_running(
+    server: &ServerConfig,
+    editor: &EditorConfig,
+    watch: &WatchConfig,
+) -> Result<bool, SupervisorError> {
+    match send_request(&ControlRequest::List) {
+        Ok(_) => return Ok(false),
+        Err(SupervisorError::NotRunning) => {}
+        Err(e) => return Err(e),
+    }
+
+    let log_path = get_log_path()?;
+
+    // A supervisor that is still starting up holds the registry entry but not yet the socket
+    let mut state = StateFile::load()?;
+    if state.cleanup_stale().is_none() && state.supervisor.is_some() {
+        wait_until_listening(&log_path)?;
+        return Ok(false);
+    }
+
+    let port = find_available_port(&server.bind, server.port_start, server.port_attempts())
+        .ok_or(SupervisorError::NoPort)?;
+
+    match daemonize(&log_path)? {
+        DaemonizeResult::Parent => {
+            wait_until_listening(&log_path)?;
+            Ok(true)
+        }
+        DaemonizeResult::Daemon => {
+            // === From here on, we're in the daemon process ===
+            let code = match run_supervisor(&server.bind, port, &log_path, editor, watch) {
+                Ok(()) => 0,
+                Err(e) => {
+                    eprintln!("Supervisor error: {}", e);
+                    1
+                }
+            };
+            std::process::exit(code);
+        }
+    }
+}
+
+/// Polls the control socket until the supervisor answers
+fn wait_until_listening(log_path: &Path) -> Result<(), SupervisorError> {
+    let deadline = Instant::now() + STARTUP_TIMEOUT;
+    loop {
+        match send_request(&ControlRequest::List) {
+            Ok(_) => return Ok(()),
+            Err(SupervisorError::NotRunning) if Instant::now() < deadline => {
+                std::thread::sleep(Duration::from_millis(50));
+            }
+            Err(SupervisorError::NotRunning) => {
+                return Err(SupervisorError::StartTimeout(log_path.to_path_buf()))
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Runs the supervisor in the current (daemonized) process
+///
+/// Serves the documents left in the registry by a previous supervisor, then accepts
+/// control connections and HTTP requests until stopped by a signal or until the last
+/// document is stopped.
+pub fn run_supervisor(
+    bind: &str,
+    port: u16,
+    log_path: &Path,
+    editor: &EditorConfig,
+    watch: &WatchConfig,
+) -> Result<(), SupervisorError> {
+    // Only one supervisor may own the socket; the lock is held until the process exits
+    let lock_path = StateFile::get_lock_path()?;
+    let lock_file = File::create(&lock_path)?;
+    if lock_file.try_lock_exclusive().is_err() {
+        return Err(SupervisorError::AlreadyRunning);
+    }
+
+    let socket_path = StateFile::get_socket_path()?;
+    let _ = fs::remove_file(&socket_path);
+    let listener = UnixListener::bind(&socket_path)?;
+
+    println!(
+        "[{}] mdview supervisor started",
+        Utc::now().format("%Y-%m-%d %H:%M:%S")
+    );
+    println!("PID: {}", get_pid());
+    println!("Port: {}", port);
+
+    let supervisor = Arc::new(Supervisor::new(bind, port, true));
+
+    let mut state = StateFile::load()?;
+    state.supervisor = Some(SupervisorInfo {
+        pid: get_pid(),
+        port,
+        started_at: Utc::now(),
+        log_file: log_path.to_path_buf(),
+    });
+    state.save()?;
+    supervisor.restore_documents(&state, editor, watch);
+
+    // Set up signal handlers for graceful shutdown
+    let shutdown = Arc::new(AtomicBool::new(false));
+    flag::register(SIGINT, Arc::clone(&shutdown))?;
+    flag::register(SIGTERM, Arc::clone(&shutdown))?;
+
+    // Shutdown monitor thread
+    let monitor_socket = socket_path.clone();
+    std::thread::spawn(move || {
+        while !shutdown.load(Ordering::Relaxed) {
+            std::thread::sleep(Duration::from_millis(100));
+        }
+        println!(
+            "[{}] Received shutdown signal",
+            Utc::now().format("%Y-%m-%d %H:%M:%S")
+        );
+        exit_supervisor(&monitor_socket);
+    });
+
+    // Control socket thread
+    let control = Arc::clone(&supervisor);
+    std::thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            if control.handle_control_connection(stream) {
+                println!(
+                    "[{}] Last document stopped, exiting",
+                    Utc::now().format("%Y-%m-%d %H:%M:%S")
+                );
+                exit_supervisor(&socket_path);
+            }
+        }
+    });
+
+    println!(
+        "[{}] Server running on {}",
+        Utc::now().format("%Y-%m-%d %H:%M:%S"),
+        base_url(bind, port)
+    );
+
+    supervisor.run_http()?;
+    drop(lock_file);
+    Ok(())
+}
+
+/// Removes the control socket and supervisor registry entry, then exits
+///
+/// Documents are left in the registry so a later supervisor can restore them.
+fn exit_supervisor(socket_path: &Path) -> ! {
+    let _ = fs::remove_file(socket_path);
+    if let Ok(mut state) = StateFile::load() {
+        state.supervisor = None;
+        let _ = state.save();
+    }
+    std::process::exit(0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::net::{TcpListener, TcpStream};
+
+    /// Creates a scratch directory holding a markdown file
+    fn scratch_file(name: &str, content: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("mdview-sup-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("doc.md");
+        fs::write(&file, content).unwrap();
+        file
+    }
+
+    /// Starts a non-persistent supervisor's HTTP server on a free port
+    fn start_supervisor() -> Arc<Supervisor> {
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let supervisor = Arc::new(Supervisor::new("127.0.0.1", port, false));
+        let running = Arc::clone(&supervisor);
+        std::thread::spawn(move || running.run_http());
+        supervisor
+    }
+
+    /// Performs a GET request and returns the raw HTTP response
+    fn get(port: u16, path: &str) -> String {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut stream = loop {
+            match TcpStream::connect(("127.0.0.1", port)) {
+                Ok(stream) => break stream,
+                Err(e) if Instant::now() > deadline => panic!("Server never came up: {}", e),
+                Err(_) => std::thread::sleep(Duration::from_millis(20)),
+            }
+        };
+        write!(
+            stream,
+            "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+            path
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    /// A request to serve a file with no editor configured and the default watch settings
+    fn serve(file: &Path) -> ControlRequest {
+        ControlRequest::Serve {
+            file: file.to_path_buf(),
+            editor: EditorConfig::default(),
+            watch: WatchConfig::default(),
+        }
+    }
+
+    fn served_url(response: ControlResponse) -> (String, bool) {
+        match response {
+            ControlResponse::Serving {
+                url,
+                already_serving,
+            } => (url, already_serving),
+            other => panic!("Expected Serving, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_control_request_json() {
+        let request = serve(Path::new("/tmp/a.md"));
+        let json = serde_json::to_string(&request).unwrap();
+        assert_eq!(
+            json,
+            r#"{"command":"serve","file":"/tmp/a.md","editor":{},"watch":{"debounce_ms":100}}"#
+        );
+
+        // Requests from before editors were sent still parse
+        let old: ControlRequest =
+            serde_json::from_str(r#"{"command":"serve","file":"/tmp/a.md"}"#).unwrap();
+        assert!(matches!(old, ControlRequest::Serve { editor, .. } if editor.command.is_none()));
+
+        let list: ControlRequest = serde_json::from_str(r#"{"command":"list"}"#).unwrap();
+        assert!(matches!(list, ControlRequest::List));
+    }
+
+    #[test]
+    fn test_serve_is_idempotent() {
+        let file = scratch_file("idempotent", "# Doc");
+        let supervisor = Supervisor::new("127.0.0.1", 6914, false);
+
+        let (first_url, first_existing) = served_url(supervisor.handle_control(serve(&file)));
+        let (second_url, second_existing) = served_url(supervisor.handle_control(serve(&file)));
+
+        assert!(first_url.starts_with("http://localhost:6914/doc/doc-"));
+        assert!(!first_existing);
+        assert_eq!(first_url, second_url);
+        assert!(second_existing);
+
+        let _ = fs::remove_dir_all(file.parent().unwrap());
+    }
+
+    #[test]
+    fn test_id_collision_keeps_documents_apart() {
+        let a = scratch_file("collide-a", "# A");
+        let b = scratch_file("collide-b", "# B");
+        let supervisor = Supervisor::new("127.0.0.1", 6914, false);
+        served_url(supervisor.handle_control(serve(&a)));
+
+        // Pretend the two paths hash to the same id
+        let b_id = generate_document_id(&b);
+        {
+            let mut documents = supervisor.documents.lock().unwrap();
+            let (_, hosted) = documents.drain().next().unwrap();
+            documents.insert(b_id.clone(), hosted);
+        }
+
+        let (url, already_serving) = served_url(supervisor.handle_control(serve(&b)));
+        assert!(!already_serving);
+        assert!(url.ends_with(&format!("/doc/{}-2/", b_id)), "{}", url);
+
+        // Stopping one file leaves the other alone
+        assert!(matches!(
+            supervisor.handle_control(ControlRequest::Stop { file: b.clone() }),
+            ControlResponse::Stopped { exiting: false }
+        ));
+        assert_eq!(supervisor.documents.lock().unwrap()[&b_id].file_path, a);
+
+        let _ = fs::remove_dir_all(a.parent().unwrap());
+        let _ = fs::remove_dir_all(b.parent().unwrap());
+    }
+
+    #[test]
+    fn test_serving_again_updates_editor() {
+        let file = scratch_file("editor", "# Doc");
+        let supervisor = start_supervisor();
+        let port = supervisor.port;
+        let (url, _) = served_url(supervisor.handle_control(serve(&file)));
+        let api = format!(
+            "{}api/open-editor",
+            url.trim_start_matches(&format!("http://localhost:{}", port))
+        );
+
+        let open_editor = || {
+            let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+            let body = "{\"line\": 1}";
+            write!(
+                stream,
+                "POST {} HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/json\r\n\
+                 Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                api,
+                body.len(),
+                body
+            )
+            .unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        };
+        get(port, "/");
+        assert!(open_editor().starts_with("HTTP/1.1 500"));
+
+        // A later `serve` with an editor configured applies to the running document
+        let request = ControlRequest::Serve {
+            file: file.clone(),
+            editor: EditorConfig {
+                command: Some("true".to_string()),
+            },
+            watch: WatchConfig::default(),
+        };
+        let (_, already_serving) = served_url(supervisor.handle_control(request));
+        assert!(already_serving);
+        let response = open_editor();
+        assert!(response.starts_with("HTTP/1.1 204"), "{}", response);
+
+        supervisor.handle_control(ControlRequest::Stop { file: file.clone() });
+        let _ = fs::remove_dir_all(file.parent().unwrap());
+    }
+
+    #[test]
+    fn test_documents_routed_by_id() {
+        let first = scratch_file("route-a", "# First document");
+        let second = scratch_file("route-b", "# Second document");
+        let supervisor = start_supervisor();
+        let port = supervisor.port;
+
+        let (first_url, _) = served_url(supervisor.handle_control(serve(&first)));
+        let (second_url, _) = served_url(supervisor.handle_control(serve(&second)));
+        let first_path = first_url.trim_start_matches(&format!("http://localhost:{}", port));
+        let second_path = second_url.trim_start_matches(&format!("http://localhost:{}", port));
+
+        assert!(get(port, first_path).contains("First document</h1>"));
+        assert!(get(port, second_path).contains("Second document</h1>"));
+
+        // The index lists both, and a bare /doc/<id> redirects to the trailing-slash URL
+        let index = get(port, "/");
+        assert!(index.contains(first_path) && index.contains(second_path));
+        let redirect = get(port, first_path.trim_end_matches('/'));
+        assert!(redirect.starts_with("HTTP/1.1 301"));
+        assert!(redirect.contains(&format!("Location: {}", first_path)));
+
+        match supervisor.handle_control(ControlRequest::Stop {
+            file: first.clone(),
+        }) {
+            ControlResponse::Stopped { exiting } => assert!(!exiting),
+            other => panic!("Expected Stopped, got {:?}", other),
+        }
+        assert!(get(port, first_path).starts_with("HTTP/1.1 404"));
+
+        match supervisor.handle_control(ControlRequest::Stop {
+            file: second.clone(),
+        }) {
+            ControlResponse::Stopped { exiting } => assert!(exiting),
+            other => panic!("Expected Stopped, got {:?}", other),
+        }
+
+        let _ = fs::remove_dir_all(first.parent().unwrap());
+        let _ = fs::remove_dir_all(second.parent().unwrap());
+    }
+
+    #[test]
+    fn test_control_connection_round_trip() {
+        let file = scratch_file("socket", "# Socket");
+        let supervisor = Supervisor::new("127.0.0.1", 6914, false);
+        let (client, server) = UnixStream::pair().unwrap();
+
+        let request = serve(&file);
+        writeln!(&client, "{}", serde_json::to_string(&request).unwrap()).unwrap();
+        assert!(!supervisor.handle_control_connection(server));
+
+        let mut line = String::new();
+        BufReader::new(&client).read_line(&mut line).unwrap();
+        let (url, _) = served_url(serde_json::from_str(&line).unwrap());
+        assert!(url.contains("/doc/doc-"));
+
+        let _ = fs::remove_dir_all(file.parent().unwrap());
+    }
+
+    #[test]
+    fn test_stop_unknown_document() {
+        let supervisor = Supervisor::new("127.0.0.1", 6914, false);
+        let response = supervisor.handle_control(ControlRequest::Stop {
+            file: PathBuf::from("/nonexistent/doc.md"),
+        });
+        assert!(matches!(response, ControlResponse::Error { .. }));
+    }
+}
@@ -1,4 +1,384 @@
-/// Module for generating HTML templates with GitHub-style markdown rendering
+//! Module for generating HTML templates with GitHub-style markdown rendering
+
+use crate::assets::{asset_url, find_asset};
+use crate::markdown::RenderedDocument;
+use crate::theme::syntax_css;
+use crate::toc::linked_toc_html;
+use std::sync::OnceLock;
+
+/// User stylesheet appended after the built-in styles, if one was configured
+static CUSTOM_CSS: OnceLock<String> = OnceLock::new();
+
+/// Sets a stylesheet to add to every page after the built-in styles
+///
+/// Only the first call has an effect; pages rendered before it don't include the styles.
+pub fn set_custom_css(css: String) {
+    let _ = CUSTOM_CSS.set(css);
+}
+
+/// Returns the `<style>` element for the custom stylesheet, or nothing if none is set
+fn custom_style_element() -> String {
+    match CUSTOM_CSS.get() {
+        Some(css) => format!("    <style>\n{}\n    </style>\n", css),
+        None => String::new(),
+    }
+}
+
+/// Script that keeps a served page in sync with its file over Server-Sent Events
+const LIVE_RELOAD_SCRIPT: &str = r#"        (function() {
+            let eventSource = null;
+            let reconnectAttempts = 0;
+            let lastMessageTime = Date.now();
+            let connectionCheckInterval = null;
+            const MAX_RECONNECT_DELAY = 30000; // 30 seconds max delay
+            const CONNECTION_TIMEOUT = 30000; // 30s without message = dead connection
+            const KEEPALIVE_CHECK_INTERVAL = 5000; // Check every 5 seconds
+
+            function connect() {
+                // Close existing connection if any
+                if (eventSource) {
+                    eventSource.close();
+                    eventSource = null;
+                }
+
+                console.log('Connecting to SSE...');
+                // Relative to the page URL, so every document streams its own updates
+                eventSource = new EventSource('?events');
+
+                eventSource.onopen = function() {
+                    console.log('SSE connected');
+                    reconnectAttempts = 0;
+                    lastMessageTime = Date.now();
+                };
+
+                eventSource.onmessage = function(event) {
+                    lastMessageTime = Date.now();
+                    if (event.data === 'keepalive') {
+                        // Keepalive received - connection is healthy
+                        console.log('Keepalive received');
+                        return;
+                    }
+
+                    let message;
+                    try {
+                        message = JSON.parse(event.data);
+                    } catch (e) {
+                        console.error('Unrecognised SSE message:', event.data);
+                        return;
+                    }
+
+                    if (message.type === 'update') {
+                        console.log('Update received');
+                        applyUpdate(message.html, message.toc);
+                        if (message.title) {
+                            document.title = message.title;
+                        }
+                    } else if (message.type === 'scroll') {
+                        scrollToLine(message.line);
+                    }
+                };
+
+                eventSource.onerror = function(error) {
+                    console.log('SSE error, connection state:', eventSource.readyState);
+
+                    // readyState: 0 = CONNECTING, 1 = OPEN, 2 = CLOSED
+                    if (eventSource.readyState === EventSource.CLOSED) {
+                        reconnect();
+                    }
+                };
+            }
+
+            // Swap in freshly rendered content without reloading the page
+            function applyUpdate(html, toc) {
+                const body = document.querySelector('.markdown-body');
+                if (!body) {
+                    location.reload();
+                    return;
+                }
+
+                const scrollX = window.scrollX;
+                const scrollY = window.scrollY;
+                // Keep the metadata table expanded or collapsed as the reader left it
+                const metadata = body.querySelector('details.front-matter');
+                const metadataOpen = metadata ? metadata.open : null;
+                body.innerHTML = html;
+                const newMetadata = body.querySelector('details.front-matter');
+                if (newMetadata && metadataOpen !== null) {
+                    newMetadata.open = metadataOpen;
+                }
+                window.scrollTo(scrollX, scrollY);
+
+                // Let page features (copy buttons, the outline, etc.) re-initialise on the
+                // new content
+                document.dispatchEvent(new CustomEvent('mdview:updated', { detail: { toc: toc } }));
+            }
+
+            // Scroll to the innermost block containing a source line (or the last one
+            // before it), placing that line about a third of the way down the window
+            function scrollToLine(line) {
+                let containing = null;
+                let preceding = null;
+                for (const el of document.querySelectorAll('.markdown-body [data-sourcepos]')) {
+                    const match = /^(\d+):\d+-(\d+):\d+$/.exec(el.getAttribute('data-sourcepos'));
+                    if (!match) {
+                        continue;
+                    }
+                    const start = parseInt(match[1], 10);
+                    const end = parseInt(match[2], 10);
+                    if (start > line) {
+                        break;
+                    }
+                    if (line <= end) {
+                        containing = { el: el, start: start, end: end };
+                    } else {
+                        preceding = { el: el, start: start, end: end };
+                    }
+                }
+
+                const target = containing || preceding;
+                if (!target) {
+                    window.scrollTo({ top: 0, behavior: 'smooth' });
+                    return;
+                }
+                // Interpolate within blocks that span several lines
+                const rect = target.el.getBoundingClientRect();
+                const fraction = containing
+                    ? (line - target.start) / (target.end - target.start + 1)
+                    : 1;
+                const top = window.scrollY + rect.top + rect.height * fraction - window.innerHeight / 3;
+                window.scrollTo({ top: Math.max(0, top), behavior: 'smooth' });
+            }
+
+            // Double-click or Ctrl/Cmd-click a block to open its source line in the
+            // configured editor
+            function openInEditor(event) {
+                const body = document.querySelector('.markdown-body');
+                const block = event.target.closest('[data-sourcepos]');
+                // Links, copy buttons and the metadata toggle keep their own behaviour
+                if (!body || !block || !body.contains(block) ||
+                        event.target.closest('a, button, summary')) {
+                    return;
+                }
+                const match = /^(\d+):/.exec(block.getAttribute('data-sourcepos'));
+                if (!match) {
+                    return;
+                }
+                event.preventDefault();
+
+                const path = location.pathname;
+                const url = path + (path.endsWith('/') ? '' : '/') + 'api/open-editor';
+                fetch(url, {
+                    method: 'POST',
+                    headers: { 'Content-Type': 'application/json' },
+                    body: JSON.stringify({ line: parseInt(match[1], 10) })
+                }).then(function(response) {
+                    if (!response.ok) {
+                        return response.text().then(showNotice);
+                    }
+                }).catch(function(error) {
+                    showNotice('Could not reach mdview: ' + error);
+                });
+            }
+
+            // Briefly show a message in the corner of the page
+            function showNotice(message) {
+                const notice = document.createElement('div');
+                notice.className = 'mdview-notice';
+                notice.textContent = message;
+                notice.style.cssText = 'position: fixed; bottom: 16px; right: 16px; ' +
+                    'max-width: 480px; padding: 8px 12px; border-radius: 6px; ' +
+                    'background: #262c36; color: #f0f6fc; font-size: 14px; z-index: 1000;';
+                document.body.appendChild(notice);
+                setTimeout(function() { notice.remove(); }, 5000);
+            }
+
+            document.addEventListener('dblclick', openInEditor);
+            document.addEventListener('click', function(event) {
+                if (event.ctrlKey || event.metaKey) {
+                    openInEditor(event);
+                }
+            });
+
+            function reconnect() {
+                if (eventSource) {
+                    eventSource.close();
+                    eventSource = null;
+                }
+
+                // Exponential backoff with max delay
+                const delay = Math.min(1000 * Math.pow(2, reconnectAttempts), MAX_RECONNECT_DELAY);
+                reconnectAttempts++;
+
+                console.log('Reconnecting in ' + delay + 'ms (attempt ' + reconnectAttempts + ')...');
+                setTimeout(connect, delay);
+            }
+
+            function checkConnectionHealth() {
+                const timeSinceLastMessage = Date.now() - lastMessageTime;
+
+                // If we haven't received ANY message (keepalive or reload) in 30s, connection is dead
+                if (timeSinceLastMessage > CONNECTION_TIMEOUT) {
+                    console.log('Connection appears dead (no messages for ' +
+                                Math.round(timeSinceLastMessage / 1000) + 's), forcing reconnection...');
+                    reconnect();
+                }
+            }
+
+            // Start connection
+            connect();
+
+            // Periodically check connection health
+            connectionCheckInterval = setInterval(checkConnectionHealth, KEEPALIVE_CHECK_INTERVAL);
+
+            // Cleanup on page unload
+            window.addEventListener('beforeunload', function() {
+                if (connectionCheckInterval) {
+                    clearInterval(connectionCheckInterval);
+                }
+                if (eventSource) {
+                    eventSource.close();
+                }
+            });
+        })();
+"#;
+
+/// Styles for the outline sidebar of document pages
+const SIDEBAR_STYLE: &str = r#"    <style>
+        .mdview-sidebar {
+            position: fixed;
+            top: 24px;
+            left: 24px;
+            width: 260px;
+            max-height: calc(100vh - 48px);
+            overflow-y: auto;
+            font-family: -apple-system, BlinkMacSystemFont, "Segoe UI", "Noto Sans", Helvetica, Arial, sans-serif;
+            font-size: 14px;
+            line-height: 1.5;
+        }
+        .mdview-sidebar summary {
+            cursor: pointer;
+            font-weight: 600;
+            margin-bottom: 4px;
+        }
+        .mdview-toc ul {
+            list-style: none;
+            margin: 0;
+            padding-left: 12px;
+        }
+        .mdview-toc > ul {
+            padding-left: 0;
+        }
+        .mdview-toc a {
+            display: block;
+            padding: 2px 8px;
+            border-left: 2px solid transparent;
+            color: #59636e;
+            text-decoration: none;
+            overflow: hidden;
+            text-overflow: ellipsis;
+            white-space: nowrap;
+        }
+        .mdview-toc a:hover {
+            color: #1f2328;
+        }
+        .mdview-toc a.active {
+            color: #0969da;
+            border-left-color: #0969da;
+        }
+        @media (prefers-color-scheme: dark) {
+            .mdview-toc a {
+                color: #9198a1;
+            }
+            .mdview-toc a:hover {
+                color: #f0f6fc;
+            }
+            .mdview-toc a.active {
+                color: #4493f8;
+                border-left-color: #4493f8;
+            }
+        }
+        /* No room beside the page: show the outline above it instead */
+        @media (max-width: 1580px) {
+            .mdview-sidebar {
+                position: static;
+                width: auto;
+                max-width: 980px;
+                max-height: none;
+                margin: 0 auto 16px;
+            }
+        }
+    </style>
+"#;
+
+/// Script that highlights the outline entry for the section being read and swaps in
+/// the new outline when the document is updated
+const SIDEBAR_SCRIPT: &str = r#"
+        (function() {
+            const sidebar = document.querySelector('.mdview-sidebar');
+            if (!sidebar) return;
+            const list = sidebar.querySelector('.mdview-toc');
+            const details = sidebar.querySelector('details');
+            // Headings within this distance of the top of the window count as current
+            const OFFSET = 80;
+            let links = [];
+            let targets = [];
+
+            // Remember whether the outline was collapsed across page loads
+            details.open = localStorage.getItem('mdview-toc-open') !== 'false';
+            details.addEventListener('toggle', function() {
+                localStorage.setItem('mdview-toc-open', details.open);
+            });
+
+            // Pair each outline link with the heading anchor it points to
+            function index() {
+                links = [];
+                targets = [];
+                for (const link of list.querySelectorAll('a')) {
+                    const target = document.getElementById(link.getAttribute('href').slice(1));
+                    if (target) {
+                        links.push(link);
+                        targets.push(target);
+                    }
+                }
+                highlight();
+            }
+
+            function highlight() {
+                let current = -1;
+                for (let i = 0; i < targets.length; i++) {
+                    if (targets[i].getBoundingClientRect().top > OFFSET) break;
+                    current = i;
+                }
+                // The last sections may be too short to ever reach the top
+                if (window.innerHeight + window.scrollY >= document.documentElement.scrollHeight - 2) {
+                    current = targets.length - 1;
+                }
+                links.forEach(function(link, i) {
+                    link.classList.toggle('active', i === current);
+                });
+            }
+
+            let pending = false;
+            window.addEventListener('scroll', function() {
+                if (pending) return;
+                pending = true;
+                requestAnimationFrame(function() {
+                    pending = false;
+                    highlight();
+                });
+            }, { passive: true });
+
+            document.addEventListener('mdview:updated', function(event) {
+                const toc = event.detail && event.detail.toc;
+                if (typeof toc === 'string') {
+                    list.innerHTML = toc;
+                    sidebar.hidden = toc === '';
+                }
+                index();
+            });
+            index();
+        })();
+"#;
 
 /// Builds a complete HTML page with GitHub markdown styling and auto-reload functionality
 ///
@@ -11,6 +391,101 @@
 ///
 /// A complete HTML document as a String
 pub fn build_html_page(markdown_html: &str, title: &str) -> String {
+    build_page(markdown_html, title, None, false)
+}
+
+/// Builds the live preview page for a rendered document
+///
+/// Like `build_html_page`, with a collapsible outline of the document's headings beside
+/// it (hidden while there are none to link to).
+///
+/// # Arguments
+///
+/// * `rendered` - The rendered document
+/// * `title` - The page title
+pub fn build_preview_page(rendered: &RenderedDocument, title: &str) -> String {
+    build_page(
+        &rendered.html,
+        title,
+        Some(&linked_toc_html(&rendered.headings)),
+        false,
+    )
+}
+
+/// Builds a self-contained HTML page for saving to disk
+///
+/// Unlike `build_html_page`, the page has no live-reload script and embeds its
+/// stylesheet (and KaTeX or mermaid.js, if the content needs them) instead of loading
+/// them from the server.
+///
+/// # Arguments
+///
+/// * `markdown_html` - The rendered markdown content as HTML
+/// * `title` - The page title (typically the filename)
+pub fn build_standalone_page(markdown_html: &str, title: &str) -> String {
+    build_page(markdown_html, title, None, true)
+}
+
+/// Returns the outline sidebar for a page, showing the given table of contents
+fn sidebar_element(toc: &str) -> String {
+    format!(
+        "    <nav class=\"mdview-sidebar\" aria-label=\"Contents\"{hidden}>
+        <details open>
+            <summary>Contents</summary>
+            <div class=\"mdview-toc\">
+{toc}            </div>
+        </details>
+    </nav>
+",
+        hidden = if toc.is_empty() { " hidden" } else { "" },
+    )
+}
+
+/// Returns an embedded asset's text, for inlining into a standalone page
+fn asset_text(name: &str) -> String {
+    find_asset(name)
+        .map(|asset| String::from_utf8_lossy(asset.data).into_owned())
+        .unwrap_or_default()
+}
+
+/// Builds a page either for serving or, if `standalone`, for saving to disk
+///
+/// A `sidebar` table of contents is shown beside the content.
+fn build_page(markdown_html: &str, title: &str, sidebar: Option<&str>, standalone: bool) -> String {
+    let (sidebar_style, sidebar, sidebar_script) = match sidebar {
+        Some(toc) => (SIDEBAR_STYLE, sidebar_element(toc), SIDEBAR_SCRIPT),
+        None => ("", String::new(), ""),
+    };
+    let has_math = markdown_html.contains("data-math-style");
+    let has_mermaid = markdown_html.contains("class=\"mermaid-diagram\"");
+
+    let (stylesheet, live_reload_script, katex_script, mermaid_script, inline_scripts);
+    if standalone {
+        stylesheet = format!("<style>\n{}    </style>", asset_text("github-markdown.css"));
+        live_reload_script = "";
+        katex_script = String::new();
+        mermaid_script = String::new();
+
+        let mut scripts = String::new();
+        for (needed, name) in [(has_math, "katex.min.js"), (has_mermaid, "mermaid.min.js")] {
+            if needed && find_asset(name).is_some() {
+                scripts.push_str(&format!("    <script>{}</script>\n", asset_text(name)));
+            }
+        }
+        inline_scripts = scripts;
+    } else {
+        stylesheet = format!(
+            "<link rel=\"stylesheet\" href=\"{}\">",
+            asset_url("github-markdown.css")
+        );
+        live_reload_script = LIVE_RELOAD_SCRIPT;
+        katex_script = asset_url("katex.min.js");
+        mermaid_script = find_asset("mermaid.min.js")
+            .map(|asset| asset_url(asset.name))
+            .unwrap_or_default();
+        inline_scripts = String::new();
+    }
+
     format!(
         r#"<!DOCTYPE html>
 <html lang="en">
@@ -19,7 +494,9 @@ pub fn build_html_page(markdown_html: &str, title: &str) -> String {
     <meta name="viewport" content="width=device-width, initial-scale=1.0">
     <meta name="color-scheme" content="light dark">
     <title>{title}</title>
-    <link rel="stylesheet" href="https://cdnjs.cloudflare.com/ajax/libs/github-markdown-css/5.5.1/github-markdown.min.css">
+    {stylesheet}
+    <style>
+{syntax_css}    </style>
     <style>
         html {{
             colors-cheme: light dark;
@@ -86,180 +563,14 @@ pub fn build_html_page(markdown_html: &str, title: &str) -> String {
         .copy-button.copied .check-icon {{
             display: block;
         }}
-        /* GitHub-style markdown alerts */
-        .markdown-body .markdown-alert {{
-            padding: 8px 16px;
-            margin-bottom: 16px;
-            color: inherit;
-            border-left: .25em solid var(--color-border-default);
-        }}
-        .markdown-body .markdown-alert > :first-child {{
-            margin-top: 0;
-        }}
-        .markdown-body .markdown-alert > :last-child {{
-            margin-bottom: 0;
-        }}
-        .markdown-body .markdown-alert-title {{
-            display: flex;
-            align-items: center;
-            gap: 8px;
-            font-weight: 500;
-        }}
-        .markdown-body .markdown-alert-title::before {{
-            content: '';
-            display: inline-block;
-            width: 16px;
-            height: 16px;
-            flex-shrink: 0;
-            background-repeat: no-repeat;
-            background-position: center;
-            background-size: 16px 16px;
-        }}
-        /* Note - blue */
-        .markdown-body .markdown-alert-note {{
-            border-left-color: var(--color-accent-emphasis);
-        }}
-        .markdown-body .markdown-alert-note .markdown-alert-title {{
-            color: var(--color-accent-fg);
-        }}
-        .markdown-body .markdown-alert-note .markdown-alert-title::before {{
-            background-image: url("data:image/svg+xml,%3Csvg xmlns='http://www.w3.org/2000/svg' viewBox='0 0 16 16' width='16' height='16'%3E%3Cpath fill='%232f81f7' d='M0 8a8 8 0 1 1 16 0A8 8 0 0 1 0 8Zm8-6.5a6.5 6.5 0 1 0 0 13 6.5 6.5 0 0 0 0-13ZM6.5 7.75A.75.75 0 0 1 7.25 7h1a.75.75 0 0 1 .75.75v2.75h.25a.75.75 0 0 1 0 1.5h-2a.75.75 0 0 1 0-1.5h.25v-2h-.25a.75.75 0 0 1-.75-.75ZM8 6a1 1 0 1 1 0-2 1 1 0 0 1 0 2Z'/%3E%3C/svg%3E");
-        }}
-        /* Tip - green */
-        .markdown-body .markdown-alert-tip {{
-            border-left-color: var(--color-success-emphasis);
-        }}
-        .markdown-body .markdown-alert-tip .markdown-alert-title {{
-            color: var(--color-success-fg);
-        }}
-        .markdown-body .markdown-alert-tip .markdown-alert-title::before {{
-            background-image: url("data:image/svg+xml,%3Csvg xmlns='http://www.w3.org/2000/svg' viewBox='0 0 16 16' width='16' height='16'%3E%3Cpath fill='%233fb950' d='M8 1.5c-2.363 0-4 1.69-4 3.75 0 .984.424 1.625.984 2.304l.214.253c.223.264.47.556.673.848.284.411.537.896.621 1.49a.75.75 0 0 1-1.484.211c-.04-.282-.163-.547-.37-.847a8.456 8.456 0 0 0-.542-.68c-.084-.1-.173-.205-.268-.32C3.201 7.75 2.5 6.766 2.5 5.25 2.5 2.31 4.863 0 8 0s5.5 2.31 5.5 5.25c0 1.516-.701 2.5-1.328 3.259-.095.115-.184.22-.268.319-.207.245-.383.453-.541.681-.208.3-.33.565-.37.847a.751.751 0 0 1-1.485-.212c.084-.593.337-1.078.621-1.489.203-.292.45-.584.673-.848.075-.088.147-.173.213-.253.561-.679.985-1.32.985-2.304 0-2.06-1.637-3.75-4-3.75ZM5.75 12h4.5a.75.75 0 0 1 0 1.5h-4.5a.75.75 0 0 1 0-1.5ZM6 15.25a.75.75 0 0 1 .75-.75h2.5a.75.75 0 0 1 0 1.5h-2.5a.75.75 0 0 1-.75-.75Z'/%3E%3C/svg%3E");
-        }}
-        /* Important - purple */
-        .markdown-body .markdown-alert-important {{
-            border-left-color: var(--color-done-emphasis);
-        }}
-        .markdown-body .markdown-alert-important .markdown-alert-title {{
-            color: var(--color-done-fg);
-        }}
-        .markdown-body .markdown-alert-important .markdown-alert-title::before {{
-            background-image: url("data:image/svg+xml,%3Csvg xmlns='http://www.w3.org/2000/svg' viewBox='0 0 16 16' width='16' height='16'%3E%3Cpath fill='%23a371f7' d='M0 1.75C0 .784.784 0 1.75 0h12.5C15.216 0 16 .784 16 1.75v9.5A1.75 1.75 0 0 1 14.25 13H8.06l-2.573 2.573A1.458 1.458 0 0 1 3 14.543V13H1.75A1.75 1.75 0 0 1 0 11.25Zm1.75-.25a.25.25 0 0 0-.25.25v9.5c0 .138.112.25.25.25h2a.75.75 0 0 1 .75.75v2.19l2.72-2.72a.749.749 0 0 1 .53-.22h6.5a.25.25 0 0 0 .25-.25v-9.5a.25.25 0 0 0-.25-.25Zm7 2.25v2.5a.75.75 0 0 1-1.5 0v-2.5a.75.75 0 0 1 1.5 0ZM9 9a1 1 0 1 1-2 0 1 1 0 0 1 2 0Z'/%3E%3C/svg%3E");
-        }}
-        /* Warning - yellow */
-        .markdown-body .markdown-alert-warning {{
-            border-left-color: var(--color-attention-emphasis);
-        }}
-        .markdown-body .markdown-alert-warning .markdown-alert-title {{
-            color: var(--color-attention-fg);
-        }}
-        .markdown-body .markdown-alert-warning .markdown-alert-title::before {{
-            background-image: url("data:image/svg+xml,%3Csvg xmlns='http://www.w3.org/2000/svg' viewBox='0 0 16 16' width='16' height='16'%3E%3Cpath fill='%23d29922' d='M6.457 1.047c.659-1.234 2.427-1.234 3.086 0l6.082 11.378A1.75 1.75 0 0 1 14.082 15H1.918a1.75 1.75 0 0 1-1.543-2.575Zm1.763.707a.25.25 0 0 0-.44 0L1.698 13.132a.25.25 0 0 0 .22.368h12.164a.25.25 0 0 0 .22-.368Zm.53 3.996v2.5a.75.75 0 0 1-1.5 0v-2.5a.75.75 0 0 1 1.5 0ZM9 11a1 1 0 1 1-2 0 1 1 0 0 1 2 0Z'/%3E%3C/svg%3E");
-        }}
-        /* Caution - red */
-        .markdown-body .markdown-alert-caution {{
-            border-left-color: var(--color-danger-emphasis);
-        }}
-        .markdown-body .markdown-alert-caution .markdown-alert-title {{
-            color: var(--color-danger-fg);
-        }}
-        .markdown-body .markdown-alert-caution .markdown-alert-title::before {{
-            background-image: url("data:image/svg+xml,%3Csvg xmlns='http://www.w3.org/2000/svg' viewBox='0 0 16 16' width='16' height='16'%3E%3Cpath fill='%23f85149' d='M4.47.22A.749.749 0 0 1 5 0h6c.199 0 .389.079.53.22l4.25 4.25c.141.14.22.331.22.53v6a.749.749 0 0 1-.22.53l-4.25 4.25A.749.749 0 0 1 11 16H5a.749.749 0 0 1-.53-.22L.22 11.53A.749.749 0 0 1 0 11V5c0-.199.079-.389.22-.53Zm.84 1.28L1.5 5.31v5.38l3.81 3.81h5.38l3.81-3.81V5.31L10.69 1.5ZM8 4a.75.75 0 0 1 .75.75v3.5a.75.75 0 0 1-1.5 0v-3.5A.75.75 0 0 1 8 4Zm0 8a1 1 0 1 1 0-2 1 1 0 0 1 0 2Z'/%3E%3C/svg%3E");
-        }}
     </style>
-</head>
+{sidebar_style}{custom_style}</head>
 <body>
-    <div class="markdown-body">
+{sidebar}    <div class="markdown-body">
         {content}
     </div>
-    <script>
-        (function() {{
-            let eventSource = null;
-            let reconnectAttempts = 0;
-            let lastMessageTime = Date.now();
-            let connectionCheckInterval = null;
-            const MAX_RECONNECT_DELAY = 30000; // 30 seconds max delay
-            const CONNECTION_TIMEOUT = 30000; // 30s without message = dead connection
-            const KEEPALIVE_CHECK_INTERVAL = 5000; // Check every 5 seconds
-
-            function connect() {{
-                // Close existing connection if any
-                if (eventSource) {{
-                    eventSource.close();
-                    eventSource = null;
-                }}
-
-                console.log('Connecting to SSE...');
-                eventSource = new EventSource('/events');
-
-                eventSource.onopen = function() {{
-                    console.log('SSE connected');
-                    reconnectAttempts = 0;
-                    lastMessageTime = Date.now();
-                }};
-
-                eventSource.onmessage = function(event) {{
-                    lastMessageTime = Date.now();
-                    if (event.data === 'reload') {{
-                        console.log('Reload signal received');
-                        location.reload();
-                    }} else if (event.data === 'keepalive') {{
-                        // Keepalive received - connection is healthy
-                        console.log('Keepalive received');
-                    }}
-                }};
-
-                eventSource.onerror = function(error) {{
-                    console.log('SSE error, connection state:', eventSource.readyState);
-
-                    // readyState: 0 = CONNECTING, 1 = OPEN, 2 = CLOSED
-                    if (eventSource.readyState === EventSource.CLOSED) {{
-                        reconnect();
-                    }}
-                }};
-            }}
-
-            function reconnect() {{
-                if (eventSource) {{
-                    eventSource.close();
-                    eventSource = null;
-                }}
-
-                // Exponential backoff with max delay
-                const delay = Math.min(1000 * Math.pow(2, reconnectAttempts), MAX_RECONNECT_DELAY);
-                reconnectAttempts++;
-
-                console.log('Reconnecting in ' + delay + 'ms (attempt ' + reconnectAttempts + ')...');
-                setTimeout(connect, delay);
-            }}
-
-            function checkConnectionHealth() {{
-                const timeSinceLastMessage = Date.now() - lastMessageTime;
-
-                // If we haven't received ANY message (keepalive or reload) in 30s, connection is dead
-                if (timeSinceLastMessage > CONNECTION_TIMEOUT) {{
-                    console.log('Connection appears dead (no messages for ' +
-                                Math.round(timeSinceLastMessage / 1000) + 's), forcing reconnection...');
-                    reconnect();
-                }}
-            }}
-
-            // Start connection
-            connect();
-
-            // Periodically check connection health
-            connectionCheckInterval = setInterval(checkConnectionHealth, KEEPALIVE_CHECK_INTERVAL);
-
-            // Cleanup on page unload
-            window.addEventListener('beforeunload', function() {{
-                if (connectionCheckInterval) {{
-                    clearInterval(connectionCheckInterval);
-                }}
-                if (eventSource) {{
-                    eventSource.close();
-                }}
-            }});
-        }})();
-
+{inline_scripts}    <script>
+{live_reload_script}{sidebar_script}
         // Copy button functionality for code blocks
         (function() {{
             const copyIcon = '<svg aria-hidden="true" height="16" viewBox="0 0 16 16" version="1.1" width="16" fill="currentColor" class="copy-icon"><path d="M0 6.75C0 5.784.784 5 1.75 5h1.5a.75.75 0 0 1 0 1.5h-1.5a.25.25 0 0 0-.25.25v7.5c0 .138.112.25.25.25h7.5a.25.25 0 0 0 .25-.25v-1.5a.75.75 0 0 1 1.5 0v1.5A1.75 1.75 0 0 1 9.25 16h-7.5A1.75 1.75 0 0 1 0 14.25Z"></path><path d="M5 1.75C5 .784 5.784 0 6.75 0h7.5C15.216 0 16 .784 16 1.75v7.5A1.75 1.75 0 0 1 14.25 11h-7.5A1.75 1.75 0 0 1 5 9.25Zm1.75-.25a.25.25 0 0 0-.25.25v7.5c0 .138.112.25.25.25h7.5a.25.25 0 0 0 .25-.25v-7.5a.25.25 0 0 0-.25-.25Z"></path></svg>';
@@ -299,15 +610,156 @@ pub fn build_html_page(markdown_html: &str, title: &str) -> String {
             }} else {{
                 initCopyButtons();
             }}
+
+            // Re-initialize after live updates replace the content
+            document.addEventListener('mdview:updated', initCopyButtons);
+        }})();
+
+        // Math typesetting with the bundled KaTeX, loaded only when a page has math
+        (function() {{
+            const katexScript = '{katex_script}';
+            let katexRequested = false;
+
+            function renderMath() {{
+                const nodes = document.querySelectorAll('.markdown-body [data-math-style]');
+                if (nodes.length === 0) return;
+
+                if (!window.katex) {{
+                    if (katexScript && !katexRequested) {{
+                        katexRequested = true;
+                        const script = document.createElement('script');
+                        script.src = katexScript;
+                        script.onload = renderMath;
+                        document.head.appendChild(script);
+                    }}
+                    return;
+                }}
+
+                nodes.forEach(function(node) {{
+                    const display = node.getAttribute('data-math-style') === 'display';
+                    const math = document.createElement(display ? 'div' : 'span');
+                    math.className = display ? 'math math-display' : 'math math-inline';
+                    // MathML output needs no fonts or stylesheet from KaTeX
+                    katex.render(node.textContent.trim(), math, {{
+                        displayMode: display,
+                        output: 'mathml',
+                        throwOnError: false
+                    }});
+                    node.replaceWith(math);
+                }});
+            }}
+
+            renderMath();
+
+            // Live updates bring back the raw TeX, so typeset it again
+            document.addEventListener('mdview:updated', renderMath);
+        }})();
+
+        // Mermaid diagrams, drawn with the bundled mermaid.js in the page's colour scheme
+        (function() {{
+            const mermaidScript = '{mermaid_script}';
+            const darkScheme = window.matchMedia('(prefers-color-scheme: dark)');
+            let mermaidRequested = false;
+            let diagramCount = 0;
+
+            function showError(container, message) {{
+                let error = container.querySelector('.diagram-error');
+                if (!error) {{
+                    error = document.createElement('div');
+                    error.className = 'diagram-error';
+                    container.insertBefore(error, container.firstChild);
+                }}
+                error.textContent = message;
+                container.classList.remove('rendered');
+            }}
+
+            async function drawDiagram(container) {{
+                const source = container.querySelector('.diagram-source').textContent;
+                const id = 'mdview-mermaid-' + (++diagramCount);
+                try {{
+                    const result = await mermaid.render(id, source);
+                    let output = container.querySelector('.diagram-output');
+                    if (!output) {{
+                        output = document.createElement('div');
+                        output.className = 'diagram-output';
+                        container.appendChild(output);
+                    }}
+                    output.innerHTML = result.svg;
+                    const error = container.querySelector('.diagram-error');
+                    if (error) error.remove();
+                    container.classList.add('rendered');
+                }} catch (err) {{
+                    // Mermaid leaves its error graphic at the end of the page; show the message inline instead
+                    const leftover = document.getElementById('d' + id);
+                    if (leftover) leftover.remove();
+                    showError(container, 'Mermaid: ' + (err && err.message ? err.message : err));
+                }}
+            }}
+
+            async function renderDiagrams() {{
+                const containers = document.querySelectorAll('.markdown-body .mermaid-diagram');
+                if (containers.length === 0) return;
+
+                if (!window.mermaid && !mermaidScript) {{
+                    containers.forEach(function(container) {{
+                        showError(container, 'Diagrams can\'t be drawn: this build of mdview doesn\'t bundle mermaid.js');
+                    }});
+                    return;
+                }}
+
+                if (!window.mermaid) {{
+                    if (!mermaidRequested) {{
+                        mermaidRequested = true;
+                        const script = document.createElement('script');
+                        script.src = mermaidScript;
+                        script.onload = renderDiagrams;
+                        document.head.appendChild(script);
+                    }}
+                    return;
+                }}
+
+                mermaid.initialize({{
+                    startOnLoad: false,
+                    securityLevel: 'strict',
+                    theme: darkScheme.matches ? 'dark' : 'default'
+                }});
+                // One at a time, since mermaid shares layout state between renders
+                for (const container of containers) {{
+                    await drawDiagram(container);
+                }}
+            }}
+
+            renderDiagrams();
+            document.addEventListener('mdview:updated', renderDiagrams);
+            // Redraw in the other theme when the system scheme changes
+            darkScheme.addEventListener('change', renderDiagrams);
         }})();
     </script>
 </body>
 </html>"#,
-        title = title,
+        title = escape_html(title),
+        stylesheet = stylesheet,
+        live_reload_script = live_reload_script,
+        inline_scripts = inline_scripts,
+        syntax_css = syntax_css(),
+        custom_style = custom_style_element(),
+        sidebar_style = sidebar_style,
+        sidebar = sidebar,
+        sidebar_script = sidebar_script,
+        katex_script = katex_script,
+        mermaid_script = mermaid_script,
         content = markdown_html
     )
 }
 
+/// Escapes text for inclusion in HTML
+pub fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -318,7 +770,76 @@ mod tests {
         assert!(html.contains("<!DOCTYPE html>"));
         assert!(html.contains("<h1>Test</h1>"));
         assert!(html.contains("Test Page"));
-        assert!(html.contains("EventSource('/events')"));
-        assert!(html.contains("github-markdown.min.css"));
+        assert!(html.contains("EventSource('?events')"));
+        assert!(html.contains("href=\"/__mdview/assets/github-markdown.css\""));
+    }
+
+    #[test]
+    fn test_live_update_patches_dom() {
+        let html = build_html_page("<p>Body</p>", "Test Page");
+        assert!(html.contains("applyUpdate(message.html, message.toc)"));
+        assert!(html.contains("mdview:updated"));
+    }
+
+    #[test]
+    fn test_preview_page_has_sidebar() {
+        let rendered = crate::markdown::render_document("# One\n\n## Two\n");
+        let html = build_preview_page(&rendered, "Test Page");
+        assert!(html.contains("<nav class=\"mdview-sidebar\" aria-label=\"Contents\">"));
+        assert!(html.contains("<li><a href=\"#two\">Two</a></li>"));
+        assert!(html.contains("event.detail && event.detail.toc"));
+
+        // Kept, but hidden, until the document has headings
+        let rendered = crate::markdown::render_document("Text");
+        let html = build_preview_page(&rendered, "Test Page");
+        assert!(html.contains("aria-label=\"Contents\" hidden>"));
+
+        assert!(!build_html_page("<p>Index</p>", "Index").contains("mdview-sidebar"));
+    }
+
+    /// Collects URLs the page would fetch from another host
+    fn external_references(html: &str) -> Vec<String> {
+        let mut found = Vec::new();
+        for marker in ["src=\"", "href=\"", "url(\"", "url('", "url(", "@import \""] {
+            for (start, _) in html.match_indices(marker) {
+                let rest = &html[start + marker.len()..];
+                let end = rest
+                    .find(|c: char| c == '"' || c == '\'' || c == ')' || c.is_whitespace())
+                    .unwrap_or(rest.len());
+                let url = &rest[..end];
+                if url.starts_with("http:") || url.starts_with("https:") || url.starts_with("//") {
+                    found.push(url.to_string());
+                }
+            }
+        }
+        found
+    }
+
+    #[test]
+    fn test_page_has_no_external_references() {
+        let markdown =
+            "# Title\n\n> [!NOTE]\n> Alert\n\n```rust\nfn main() {}\n```\n\n![local](img.png)\n\n$$x^2$$\n";
+        let html = build_html_page(&crate::markdown::convert_markdown(markdown), "Test Page");
+        assert_eq!(external_references(&html), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_mermaid_script_only_when_bundled() {
+        let html = build_html_page("<div class=\"mermaid-diagram\"></div>", "Test Page");
+        let expected = match find_asset("mermaid.min.js") {
+            Some(_) => "const mermaidScript = '/__mdview/assets/mermaid.min.js';",
+            None => "const mermaidScript = '';",
+        };
+        assert!(html.contains(expected));
+        assert!(html.contains("addEventListener('mdview:updated', renderDiagrams)"));
+    }
+
+    #[test]
+    fn test_math_uses_bundled_katex() {
+        let html = build_html_page("<span data-math-style=\"inline\">x</span>", "Test Page");
+        assert!(html.contains("const katexScript = '/__mdview/assets/katex.min.js';"));
+        assert!(html.contains("output: 'mathml'"));
+        // Typesetting is repeated after every live update
+        assert!(html.contains("addEventListener('mdview:updated', renderMath)"));
     }
 }
@@ -0,0 +1,215 @@
+/// Module for syntax highlighting themes and the stylesheet generated from them
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use syntect::highlighting::{Color, Theme, ThemeSet};
+use syntect::html::{css_for_theme_with_class_style, ClassStyle};
+use two_face::theme::LazyThemeSet;
+
+/// Prefix added to every highlighting class, so scope names such as `string` or
+/// `source` can't collide with the page's own styles
+pub const CLASS_PREFIX: &str = "hl-";
+
+/// Theme used for code blocks when the page is light
+pub const DEFAULT_LIGHT_THEME: &str = "GitHub";
+
+/// Theme used for code blocks when the page is dark
+pub const DEFAULT_DARK_THEME: &str = "gh-dark";
+
+/// Custom gh-dark theme, bundled at compile time
+const GH_DARK_THEME_DATA: &[u8] = include_bytes!("../assets/gh-dark.tmTheme");
+
+/// Stylesheet for highlighted code, generated once from the configured themes
+static SYNTAX_CSS: OnceLock<String> = OnceLock::new();
+
+#[derive(Debug)]
+pub enum ThemeError {
+    /// The name isn't a bundled theme and doesn't point at a file
+    Unknown(String),
+    Load(PathBuf, syntect::LoadingError),
+    Css(syntect::Error),
+    /// `configure_themes` was called after the stylesheet was already in use
+    AlreadyConfigured,
+}
+
+impl std::fmt::Display for ThemeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ThemeError::Unknown(name) => write!(
+                f,
+                "Unknown theme '{}'. Use a path to a .tmTheme file or one of: {}",
+                name,
+                bundled_theme_names().join(", ")
+            ),
+            ThemeError::Load(path, e) => {
+                write!(f, "Could not load theme '{}': {}", path.display(), e)
+            }
+            ThemeError::Css(e) => write!(f, "Could not generate theme CSS: {}", e),
+            ThemeError::AlreadyConfigured => write!(f, "Themes were already configured"),
+        }
+    }
+}
+
+impl std::error::Error for ThemeError {}
+
+/// Returns the bundled theme set (bat's themes, loaded lazily one at a time)
+fn bundled_themes() -> &'static LazyThemeSet {
+    static THEMES: OnceLock<LazyThemeSet> = OnceLock::new();
+    THEMES.get_or_init(|| LazyThemeSet::from(two_face::theme::extra()))
+}
+
+/// Lists the names of every bundled theme
+pub fn bundled_theme_names() -> Vec<&'static str> {
+    let mut names: Vec<&str> = bundled_themes().theme_names().collect();
+    names.push(DEFAULT_DARK_THEME);
+    names.sort_unstable_by_key(|name| name.to_lowercase());
+    names
+}
+
+/// Loads a theme by bundled name or from a `.tmTheme` file
+///
+/// # Arguments
+///
+/// * `name` - A bundled theme name (see `bundled_theme_names`) or a path to a `.tmTheme` file
+///
+/// # Errors
+///
+/// Returns an error if the name is unknown or the file can't be parsed
+pub fn load_theme(name: &str) -> Result<Theme, ThemeError> {
+    let path = Path::new(name);
+    if path.is_file() {
+        return ThemeSet::get_theme(path).map_err(|e| ThemeError::Load(path.to_path_buf(), e));
+    }
+
+    if name == DEFAULT_DARK_THEME {
+        return Ok(
+            ThemeSet::load_from_reader(&mut Cursor::new(GH_DARK_THEME_DATA))
+                .expect("Failed to load bundled gh-dark theme"),
+        );
+    }
+
+    bundled_themes()
+        .get(name)
+        .cloned()
+        .ok_or_else(|| ThemeError::Unknown(name.to_string()))
+}
+
+/// Generates the stylesheet for highlighted code
+///
+/// The light theme's rules apply by default and the dark theme's rules are wrapped in a
+/// `prefers-color-scheme: dark` media query, so code blocks follow the page's scheme.
+///
+/// # Errors
+///
+/// Returns an error if syntect can't generate CSS for either theme
+pub fn syntax_css_for(light: &Theme, dark: &Theme) -> Result<String, ThemeError> {
+    let mut css = theme_css(light)?;
+    css.push_str("@media (prefers-color-scheme: dark) {\n");
+    css.push_str(&theme_css(dark)?);
+    css.push_str("}\n");
+    Ok(css)
+}
+
+/// Generates the token and code block rules for a single theme
+fn theme_css(theme: &Theme) -> Result<String, ThemeError> {
+    let class_style = ClassStyle::SpacedPrefixed {
+        prefix: CLASS_PREFIX,
+    };
+    let mut css = String::new();
+
+    // Code blocks take the theme's own colours rather than the page's
+    let mut block_rules = Vec::new();
+    if let Some(fg) = theme.settings.foreground {
+        block_rules.push(format!("color: {};", hex(fg)));
+    }
+    if let Some(bg) = theme.settings.background {
+        block_rules.push(format!("background-color: {};", hex(bg)));
+    }
+    if !block_rules.is_empty() {
+        css.push_str(&format!(
+            ".markdown-body pre.syntax-highlighting {{ {} }}\n",
+            block_rules.join(" ")
+        ));
+    }
+
+    css.push_str(&css_for_theme_with_class_style(theme, class_style).map_err(ThemeError::Css)?);
+    Ok(css)
+}
+
+/// Formats a theme colour as a CSS hex colour
+fn hex(color: Color) -> String {
+    format!("#{:02x}{:02x}{:02x}", color.r, color.g, color.b)
+}
+
+/// Chooses the themes the syntax stylesheet is generated from
+///
+/// Must be called before the first page is rendered; afterwards the stylesheet is fixed
+/// for the life of the process.
+///
+/// # Arguments
+///
+/// * `light` - Theme name or `.tmTheme` path used when the page is light
+/// * `dark` - Theme name or `.tmTheme` path used when the page is dark
+pub fn configure_themes(light: &str, dark: &str) -> Result<(), ThemeError> {
+    let css = syntax_css_for(&load_theme(light)?, &load_theme(dark)?)?;
+    SYNTAX_CSS
+        .set(css)
+        .map_err(|_| ThemeError::AlreadyConfigured)
+}
+
+/// Returns the syntax stylesheet, generating it from the default themes if none were configured
+pub fn syntax_css() -> &'static str {
+    SYNTAX_CSS.get_or_init(|| {
+        let light = load_theme(DEFAULT_LIGHT_THEME).expect("Default light theme is bundled");
+        let dark = load_theme(DEFAULT_DARK_THEME).expect("Default dark theme is bundled");
+        syntax_css_for(&light, &dark).expect("Default themes produce valid CSS")
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_themes_are_bundled() {
+        assert!(load_theme(DEFAULT_LIGHT_THEME).is_ok());
+        assert!(load_theme(DEFAULT_DARK_THEME).is_ok());
+        assert!(bundled_theme_names().contains(&"Nord"));
+    }
+
+    #[test]
+    fn test_unknown_theme() {
+        let err = load_theme("no-such-theme").unwrap_err();
+        assert!(matches!(err, ThemeError::Unknown(_)));
+        assert!(err.to_string().contains("gh-dark"));
+    }
+
+    #[test]
+    fn test_theme_from_file() {
+        let path =
+            std::env::temp_dir().join(format!("mdview-theme-{}.tmTheme", std::process::id()));
+        std::fs::write(&path, GH_DARK_THEME_DATA).unwrap();
+        assert!(load_theme(path.to_str().unwrap()).is_ok());
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_css_follows_color_scheme() {
+        let light_theme = load_theme(DEFAULT_LIGHT_THEME).unwrap();
+        let dark_theme = load_theme(DEFAULT_DARK_THEME).unwrap();
+        let css = syntax_css_for(&light_theme, &dark_theme).unwrap();
+
+        let (light, dark) = css
+            .split_once("@media (prefers-color-scheme: dark)")
+            .expect("Dark rules should be behind a media query");
+        assert!(light.contains(".hl-keyword"));
+        assert!(dark.contains(".hl-keyword"));
+
+        // Each half gives code blocks its own theme's background
+        let light_bg = hex(light_theme.settings.background.unwrap());
+        let dark_bg = hex(dark_theme.settings.background.unwrap());
+        assert_ne!(light_bg, dark_bg);
+        assert!(light.contains(&format!("background-color: {};", light_bg)));
+        assert!(dark.contains(&format!("background-color: {};", dark_bg)));
+    }
+}
@@ -0,0 +1,298 @@
+/// Module for building tables of contents from a document's headings
+use crate::markdown::{collect_headings, extensions, with_syntax_tree, Heading};
+use crate::template::escape_html;
+use comrak::nodes::NodeValue;
+use std::error::Error;
+use std::fmt;
+
+/// Comment that starts the table of contents region updated by `update_toc`
+pub const TOC_START: &str = "<!-- toc -->";
+
+/// Comment that ends the table of contents region
+pub const TOC_END: &str = "<!-- tocstop -->";
+
+/// Errors that can occur when updating a document's table of contents
+#[derive(Debug, PartialEq, Eq)]
+pub enum TocError {
+    /// The document has no start marker
+    MissingStart,
+    /// The document has a start marker with no end marker after it
+    MissingEnd,
+}
+
+impl fmt::Display for TocError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TocError::MissingStart => write!(
+                f,
+                "No '{}' marker found; add '{}' and '{}' lines where the table of contents should go",
+                TOC_START, TOC_START, TOC_END
+            ),
+            TocError::MissingEnd => write!(f, "No '{}' marker after '{}'", TOC_END, TOC_START),
+        }
+    }
+}
+
+impl Error for TocError {}
+
+/// Renders headings as a nested list of links to their anchors
+///
+/// Each heading is nested under the closest preceding heading of a higher level, so
+/// a document that skips a level (`#` then `###`) still nests one step at a time.
+///
+/// # Returns
+///
+/// A `<ul>` element, or an empty string if there are no headings
+pub fn toc_html(headings: &[Heading]) -> String {
+    let mut html = String::new();
+    // Heading levels of the lists that are currently open
+    let mut open: Vec<u8> = Vec::new();
+
+    for heading in headings {
+        match open.last() {
+            None => html.push_str("<ul>\n<li>"),
+            Some(&level) if heading.level > level => html.push_str("\n<ul>\n<li>"),
+            Some(_) => {
+                while open.len() > 1 && heading.level < open[open.len() - 1] {
+                    html.push_str("</li>\n</ul>\n");
+                    open.pop();
+                }
+                if heading.level > open[open.len() - 1] {
+                    // Back out of a deeper list to a level between it and its parent
+                    html.push_str("\n<ul>\n<li>");
+                } else {
+                    html.push_str("</li>\n<li>");
+                    open.pop();
+                }
+            }
+        }
+        open.push(heading.level);
+        html.push_str(&format!(
+            "<a href=\"#{}\">{}</a>",
+            escape_html(&heading.anchor),
+            escape_html(&heading.text)
+        ));
+    }
+
+    for _ in &open {
+        html.push_str("</li>\n</ul>\n");
+    }
+    html
+}
+
+/// Like `toc_html`, but empty unless the `header_ids` extension is on, since headings
+/// have no anchors to link to without it
+pub fn linked_toc_html(headings: &[Heading]) -> String {
+    if extensions().header_ids {
+        toc_html(headings)
+    } else {
+        String::new()
+    }
+}
+
+/// Renders headings as a nested markdown list of links to their anchors
+///
+/// Headings nest like `toc_html`'s, indented two spaces per level. Anchors are
+/// comrak's, which match the ids GitHub gives headings.
+pub fn toc_markdown(headings: &[Heading]) -> String {
+    let mut markdown = String::new();
+    // Heading levels of the enclosing list items
+    let mut open: Vec<u8> = Vec::new();
+
+    for heading in headings {
+        while open.last().is_some_and(|&level| level >= heading.level) {
+            open.pop();
+        }
+        markdown.push_str(&format!(
+            "{}- [{}](#{})\n",
+            "  ".repeat(open.len()),
+            escape_link_text(&heading.text),
+            heading.anchor
+        ));
+        open.push(heading.level);
+    }
+    markdown
+}
+
+/// Escapes the characters that would end a link's text or format it
+fn escape_link_text(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '\\' | '[' | ']' | '*' | '_' | '`' | '<') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Regenerates the table of contents between a document's `<!-- toc -->` and
+/// `<!-- tocstop -->` markers
+///
+/// The markers must be HTML blocks of their own, so examples of them in code blocks
+/// are left alone. Only the first pair is updated.
+///
+/// # Arguments
+///
+/// * `content` - The markdown document
+/// * `max_depth` - The deepest heading level to list, from 1 (`#`) to 6 (`######`)
+///
+/// # Returns
+///
+/// The document with its table of contents replaced; it's unchanged if the table was
+/// already up to date
+///
+/// # Errors
+///
+/// Returns an error if the document doesn't have both markers
+pub fn update_toc(content: &str, max_depth: u8) -> Result<String, TocError> {
+    let (headings, markers) = with_syntax_tree(content, |root| {
+        let markers: Vec<(&str, usize)> = root
+            .descendants()
+            .filter_map(|node| {
+                let ast = node.data();
+                let NodeValue::HtmlBlock(block) = &ast.value else {
+                    return None;
+                };
+                [TOC_START, TOC_END]
+                    .into_iter()
+                    .find(|marker| block.literal.trim() == *marker)
+                    .map(|marker| (marker, ast.sourcepos.start.line))
+            })
+            .collect();
+        (collect_headings(root), markers)
+    });
+
+    let start = markers
+        .iter()
+        .find(|(marker, _)| *marker == TOC_START)
+        .map(|&(_, line)| line)
+        .ok_or(TocError::MissingStart)?;
+    let end = markers
+        .iter()
+        .find(|&&(marker, line)| marker == TOC_END && line > start)
+        .map(|&(_, line)| line)
+        .ok_or(TocError::MissingEnd)?;
+
+    let headings: Vec<Heading> = headings
+        .into_iter()
+        .filter(|heading| heading.level <= max_depth)
+        .collect();
+    let toc = toc_markdown(&headings);
+
+    // Keep the marker lines (and the rest of the file) byte for byte
+    let lines: Vec<&str> = content.split_inclusive('\n').collect();
+    let mut updated = lines[..start].concat();
+    updated.push('\n');
+    if !toc.is_empty() {
+        updated.push_str(&toc);
+        updated.push('\n');
+    }
+    updated.push_str(&lines[end - 1..].concat());
+    Ok(updated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn heading(level: u8, text: &str) -> Heading {
+        Heading {
+            level,
+            text: text.to_string(),
+            anchor: text.to_lowercase(),
+        }
+    }
+
+    #[test]
+    fn test_empty() {
+        assert_eq!(toc_html(&[]), "");
+    }
+
+    #[test]
+    fn test_nesting() {
+        let html = toc_html(&[
+            heading(1, "A"),
+            heading(2, "B"),
+            heading(3, "C"),
+            heading(2, "D"),
+            heading(1, "E"),
+        ]);
+        assert_eq!(
+            html,
+            "<ul>\n<li><a href=\"#a\">A</a>\n\
+             <ul>\n<li><a href=\"#b\">B</a>\n\
+             <ul>\n<li><a href=\"#c\">C</a></li>\n</ul>\n\
+             </li>\n<li><a href=\"#d\">D</a></li>\n</ul>\n\
+             </li>\n<li><a href=\"#e\">E</a></li>\n</ul>\n"
+        );
+    }
+
+    #[test]
+    fn test_skipped_and_uneven_levels() {
+        // `###` under `#`, then a `##` that sits between them
+        let html = toc_html(&[heading(1, "A"), heading(3, "B"), heading(2, "C")]);
+        assert_eq!(
+            html,
+            "<ul>\n<li><a href=\"#a\">A</a>\n\
+             <ul>\n<li><a href=\"#b\">B</a></li>\n</ul>\n\
+             \n<ul>\n<li><a href=\"#c\">C</a></li>\n</ul>\n\
+             </li>\n</ul>\n"
+        );
+
+        // A document that starts below its top level
+        let html = toc_html(&[heading(2, "A"), heading(1, "<B>")]);
+        assert_eq!(
+            html,
+            "<ul>\n<li><a href=\"#a\">A</a></li>\n<li><a href=\"#&lt;b&gt;\">&lt;B&gt;</a></li>\n</ul>\n"
+        );
+    }
+
+    #[test]
+    fn test_toc_markdown() {
+        let markdown = toc_markdown(&[
+            heading(2, "A"),
+            heading(4, "B"),
+            heading(3, "C"),
+            heading(2, "[D]_*"),
+            heading(1, "E"),
+        ]);
+        assert_eq!(
+            markdown,
+            "- [A](#a)\n  - [B](#b)\n  - [C](#c)\n- [\\[D\\]\\_\\*](#[d]_*)\n- [E](#e)\n"
+        );
+    }
+
+    #[test]
+    fn test_update_toc() {
+        let content = "# Guide\n\n<!-- toc -->\n- [Stale](#stale)\n<!-- tocstop -->\n\n\
+                       ## Install\n\n### From source\n\n## Usage\n\n## Usage\n";
+        let updated = update_toc(content, 6).unwrap();
+        assert_eq!(
+            updated,
+            "# Guide\n\n<!-- toc -->\n\n\
+             - [Guide](#guide)\n  - [Install](#install)\n    - [From source](#from-source)\n  \
+             - [Usage](#usage)\n  - [Usage](#usage-1)\n\n\
+             <!-- tocstop -->\n\n## Install\n\n### From source\n\n## Usage\n\n## Usage\n"
+        );
+        // Up to date tables are left alone
+        assert_eq!(update_toc(&updated, 6).unwrap(), updated);
+
+        let shallow = update_toc(content, 2).unwrap();
+        assert!(shallow.contains("- [Guide](#guide)\n  - [Install](#install)\n  - [Usage]"));
+        assert!(!shallow.contains("From source](#"));
+    }
+
+    #[test]
+    fn test_update_toc_needs_markers() {
+        assert_eq!(update_toc("# A\n", 6), Err(TocError::MissingStart));
+        assert_eq!(
+            update_toc("# A\n<!-- tocstop -->\n<!-- toc -->\n", 6),
+            Err(TocError::MissingEnd)
+        );
+
+        // Markers in code blocks are only examples
+        let content = "# A\n\n```markdown\n<!-- toc -->\n<!-- tocstop -->\n```\n";
+        assert_eq!(update_toc(content, 6), Err(TocError::MissingStart));
+    }
+}
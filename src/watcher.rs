@@ -1,20 +1,36 @@
 /// Module for watching markdown files and detecting changes
-use crossbeam_channel::Sender;
+use crossbeam_channel::{never, select, unbounded, Receiver, RecvTimeoutError, Sender};
+use notify::event::{ModifyKind, RenameMode};
 use notify::{Config, Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
 use std::error::Error;
-use std::path::PathBuf;
-use std::sync::mpsc::channel as std_channel;
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// How often to retry watching the parent directory after it disappears
+const REWATCH_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Default quiet period used to coalesce a burst of file events into one reload
+pub const DEFAULT_DEBOUNCE: Duration = Duration::from_millis(100);
 
 /// Watches a single markdown file for changes and sends reload signals
 ///
-/// This function sets up a file watcher that monitors the specified file for modifications.
-/// When changes are detected, it sends a message through the provided channel to trigger
-/// a reload.
+/// This function watches the file's parent directory rather than the file itself, so that
+/// editors which save by writing a temporary file and renaming it over the original
+/// (vim, JetBrains IDEs, VS Code "safe write") keep triggering reloads after the inode
+/// is replaced. Events are filtered down to the watched file name.
+///
+/// A single save usually produces several events, so changes are debounced: a reload is
+/// only sent once no further events have arrived for `debounce`. A reload is sent even
+/// if the file's content is unchanged, since saving the same bytes must still replace
+/// content an editor pushed to the preview; receivers skip re-rendering identical
+/// content themselves.
 ///
 /// # Arguments
 ///
 /// * `path` - The path to the markdown file to watch
 /// * `reload_tx` - Channel sender for sending reload signals
+/// * `debounce` - Quiet period to wait for before sending a reload
 ///
 /// # Returns
 ///
@@ -23,49 +39,453 @@ use std::sync::mpsc::channel as std_channel;
 /// # Errors
 ///
 /// Returns an error if the file watcher cannot be created or if there are issues watching the file
-pub fn watch_file(path: PathBuf, reload_tx: Sender<()>) -> Result<(), Box<dyn Error>> {
-    let (tx, rx) = std_channel();
+pub fn watch_file(
+    path: PathBuf,
+    reload_tx: Sender<()>,
+    debounce: Duration,
+) -> Result<(), Box<dyn Error>> {
+    watch_file_until(path, reload_tx, debounce, never())
+}
+
+/// Watches a single markdown file like `watch_file`, until told to stop
+///
+/// Watching ends as soon as `stop` receives a message or its sender is dropped, which
+/// lets long-running hosts release the watch when a document is no longer served.
+///
+/// # Arguments
+///
+/// * `path` - The path to the markdown file to watch
+/// * `reload_tx` - Channel sender for sending reload signals
+/// * `debounce` - Quiet period to wait for before sending a reload
+/// * `stop` - Channel that ends the watch when it fires or disconnects
+///
+/// # Errors
+///
+/// Returns an error if the file watcher cannot be created or if there are issues watching the file
+pub fn watch_file_until(
+    path: PathBuf,
+    reload_tx: Sender<()>,
+    debounce: Duration,
+    stop: Receiver<()>,
+) -> Result<(), Box<dyn Error>> {
+    let file_name = path
+        .file_name()
+        .ok_or("Watched path has no file name")?
+        .to_os_string();
+    let dir = path
+        .parent()
+        .ok_or("Watched path has no parent directory")?
+        .to_path_buf();
+
+    let (tx, rx) = unbounded();
 
     let mut watcher = RecommendedWatcher::new(
         move |res: Result<Event, notify::Error>| {
-            if let Ok(event) = res {
-                let _ = tx.send(event);
-            }
+            let _ = tx.send(res);
         },
         Config::default(),
     )?;
 
-    // Watch the file for changes
-    watcher.watch(&path, RecursiveMode::NonRecursive)?;
+    // Watch the containing directory so renames over the file are seen
+    watcher.watch(&dir, RecursiveMode::NonRecursive)?;
+
+    let filename = file_name.to_str().unwrap_or("file");
 
-    let filename = path.file_name().and_then(|n| n.to_str()).unwrap_or("file");
+    // Whether a change has been seen and we're waiting for the burst to settle
+    let mut pending = false;
 
     println!("Watching for changes...");
 
     // Block and wait for file change events
     loop {
-        match rx.recv() {
-            Ok(event) => {
-                // Only process modify events
-                if matches!(event.kind, EventKind::Modify(_)) {
-                    match reload_tx.send(()) {
-                        Ok(_) => {
-                            println!("Refreshed: {}", filename);
-                        }
-                        Err(e) => {
-                            eprintln!("Error sending reload signal: {}", e);
-                            // If the receiver is dropped, we should exit
-                            break;
-                        }
+        let received = if pending {
+            select! {
+                recv(rx) -> msg => msg.map_err(|_| RecvTimeoutError::Disconnected),
+                recv(stop) -> _ => break,
+                default(debounce) => Err(RecvTimeoutError::Timeout),
+            }
+        } else {
+            select! {
+                recv(rx) -> msg => msg.map_err(|_| RecvTimeoutError::Disconnected),
+                recv(stop) -> _ => break,
+            }
+        };
+
+        match received {
+            Ok(Ok(event)) => {
+                if is_dir_removed(&event, &dir) {
+                    eprintln!("Watched directory was removed, waiting for it to return...");
+                    if !rewatch(&mut watcher, &dir, &stop) {
+                        break;
                     }
+                    // The file may have been recreated while we weren't watching
+                    pending = true;
+                } else if is_change_event(&event, &file_name) {
+                    pending = true;
                 }
             }
-            Err(e) => {
+            Ok(Err(e)) => {
                 eprintln!("Watch error: {}", e);
-                // Continue watching even if there's an error
+                if !rewatch(&mut watcher, &dir, &stop) {
+                    break;
+                }
+                pending = true;
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                // The burst has settled; reload unless the file is gone mid-save
+                pending = false;
+                if !path.is_file() {
+                    continue;
+                }
+
+                match reload_tx.send(()) {
+                    Ok(_) => {
+                        println!("Refreshed: {}", filename);
+                    }
+                    Err(e) => {
+                        eprintln!("Error sending reload signal: {}", e);
+                        // If the receiver is dropped, we should exit
+                        break;
+                    }
+                }
+            }
+            Err(RecvTimeoutError::Disconnected) => {
+                // Watcher callback was dropped, nothing more will arrive
+                break;
             }
         }
     }
 
     Ok(())
 }
+
+/// Watches a directory tree and signals when files are added, removed or renamed
+///
+/// Content changes are ignored; they are picked up by each document's own
+/// `watch_file` watcher. Bursts of events (a `git checkout`, say) are coalesced, and a
+/// signal is sent once no further events have arrived for `debounce`.
+///
+/// # Arguments
+///
+/// * `root` - The directory to watch recursively
+/// * `change_tx` - Channel sender for sending change signals
+/// * `debounce` - Quiet period to wait for before sending a signal
+///
+/// # Errors
+///
+/// Returns an error if the watcher cannot be created or the directory cannot be watched
+pub fn watch_tree(
+    root: PathBuf,
+    change_tx: Sender<()>,
+    debounce: Duration,
+) -> Result<(), Box<dyn Error>> {
+    let (tx, rx) = unbounded();
+
+    let mut watcher = RecommendedWatcher::new(
+        move |res: Result<Event, notify::Error>| {
+            let _ = tx.send(res);
+        },
+        Config::default(),
+    )?;
+    watcher.watch(&root, RecursiveMode::Recursive)?;
+
+    let mut pending = false;
+    loop {
+        let received = if pending {
+            rx.recv_timeout(debounce)
+        } else {
+            rx.recv().map_err(|_| RecvTimeoutError::Disconnected)
+        };
+
+        match received {
+            Ok(Ok(event)) => {
+                if is_structure_event(&event) {
+                    pending = true;
+                }
+            }
+            Ok(Err(e)) => eprintln!("Watch error: {}", e),
+            Err(RecvTimeoutError::Timeout) => {
+                pending = false;
+                if change_tx.send(()).is_err() {
+                    break;
+                }
+            }
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks whether an event adds, removes or renames an entry
+fn is_structure_event(event: &Event) -> bool {
+    matches!(
+        event.kind,
+        EventKind::Create(_) | EventKind::Remove(_) | EventKind::Modify(ModifyKind::Name(_))
+    )
+}
+
+/// Checks whether an event means the watched file now has new content
+///
+/// Creations and renames onto the file count as changes, as do ordinary writes.
+/// Removals and renames away from the file are ignored; the replacement that usually
+/// follows will be reported as its own event.
+fn is_change_event(event: &Event, file_name: &OsStr) -> bool {
+    let is_target = |path: &PathBuf| path.file_name() == Some(file_name);
+
+    match event.kind {
+        EventKind::Create(_) => event.paths.iter().any(is_target),
+        EventKind::Modify(ModifyKind::Name(RenameMode::From)) => false,
+        EventKind::Modify(ModifyKind::Name(RenameMode::Both)) => {
+            // Paths are [from, to]; only the destination matters
+            event.paths.last().is_some_and(is_target)
+        }
+        EventKind::Modify(_) => event.paths.iter().any(is_target),
+        _ => false,
+    }
+}
+
+/// Checks whether an event reports that the watched directory itself is gone
+fn is_dir_removed(event: &Event, dir: &Path) -> bool {
+    let moved_away = matches!(
+        event.kind,
+        EventKind::Modify(ModifyKind::Name(RenameMode::From))
+    );
+    (matches!(event.kind, EventKind::Remove(_)) || moved_away)
+        && event.paths.iter().any(|p| p == dir)
+}
+
+/// Re-establishes the directory watch, waiting for the directory to exist again
+///
+/// Returns false if `stop` fired while waiting.
+fn rewatch(watcher: &mut RecommendedWatcher, dir: &Path, stop: &Receiver<()>) -> bool {
+    let _ = watcher.unwatch(dir);
+    loop {
+        if dir.is_dir() && watcher.watch(dir, RecursiveMode::NonRecursive).is_ok() {
+            return true;
+        }
+        if !matches!(
+            stop.recv_timeout(REWATCH_INTERVAL),
+            Err(RecvTimeoutError::Timeout)
+        ) {
+            return false;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use notify::event::{CreateKind, DataChange, RemoveKind};
+    use std::fs;
+    use std::io::Write;
+
+    /// Creates an empty scratch directory unique to this test
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("mdview-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Starts watching `path` in the background and waits for the watch to be in place
+    fn start_watching(path: &Path) -> Receiver<()> {
+        let (reload_tx, reload_rx) = unbounded();
+        let path = path.to_path_buf();
+        std::thread::spawn(move || {
+            let _ = watch_file(path, reload_tx, DEFAULT_DEBOUNCE);
+        });
+        std::thread::sleep(Duration::from_millis(300));
+        reload_rx
+    }
+
+    /// Writes `content` to a temp file and renames it over `path`, like vim and JetBrains do
+    fn rename_over(path: &Path, content: &str) {
+        let tmp = path.with_extension("md.tmp");
+        fs::write(&tmp, content).unwrap();
+        fs::rename(&tmp, path).unwrap();
+    }
+
+    fn event(kind: EventKind, paths: &[&str]) -> Event {
+        let mut event = Event::new(kind);
+        for path in paths {
+            event = event.add_path(PathBuf::from(path));
+        }
+        event
+    }
+
+    #[test]
+    fn test_is_change_event() {
+        let name = OsStr::new("doc.md");
+
+        let write = event(
+            EventKind::Modify(ModifyKind::Data(DataChange::Content)),
+            &["/d/doc.md"],
+        );
+        let create = event(EventKind::Create(CreateKind::File), &["/d/doc.md"]);
+        let rename_onto = event(
+            EventKind::Modify(ModifyKind::Name(RenameMode::Both)),
+            &["/d/doc.md.tmp", "/d/doc.md"],
+        );
+        let rename_to = event(
+            EventKind::Modify(ModifyKind::Name(RenameMode::To)),
+            &["/d/doc.md"],
+        );
+        assert!(is_change_event(&write, name));
+        assert!(is_change_event(&create, name));
+        assert!(is_change_event(&rename_onto, name));
+        assert!(is_change_event(&rename_to, name));
+
+        let other_file = event(
+            EventKind::Modify(ModifyKind::Data(DataChange::Content)),
+            &["/d/other.md"],
+        );
+        let rename_away = event(
+            EventKind::Modify(ModifyKind::Name(RenameMode::Both)),
+            &["/d/doc.md", "/d/doc.md~"],
+        );
+        let remove = event(EventKind::Remove(RemoveKind::File), &["/d/doc.md"]);
+        assert!(!is_change_event(&other_file, name));
+        assert!(!is_change_event(&rename_away, name));
+        assert!(!is_change_event(&remove, name));
+    }
+
+    #[test]
+    fn test_survives_rename_over_save() {
+        let dir = scratch_dir("rename");
+        let path = dir.join("doc.md");
+        fs::write(&path, "# One").unwrap();
+
+        let reload_rx = start_watching(&path);
+
+        // Each save replaces the inode; the watch must keep working after the first
+        for content in ["# Two", "# Three"] {
+            rename_over(&path, content);
+            assert!(
+                reload_rx.recv_timeout(Duration::from_secs(5)).is_ok(),
+                "No reload after rename-over save"
+            );
+            while reload_rx.recv_timeout(Duration::from_millis(200)).is_ok() {}
+        }
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_survives_delete_and_recreate() {
+        let dir = scratch_dir("recreate");
+        let path = dir.join("doc.md");
+        fs::write(&path, "# One").unwrap();
+
+        let reload_rx = start_watching(&path);
+
+        fs::remove_file(&path).unwrap();
+        fs::write(&path, "# Two").unwrap();
+        assert!(
+            reload_rx.recv_timeout(Duration::from_secs(5)).is_ok(),
+            "No reload after file was recreated"
+        );
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_ignores_sibling_files() {
+        let dir = scratch_dir("siblings");
+        let path = dir.join("doc.md");
+        fs::write(&path, "# One").unwrap();
+
+        let reload_rx = start_watching(&path);
+
+        fs::write(dir.join("other.md"), "# Other").unwrap();
+        assert!(reload_rx.recv_timeout(Duration::from_millis(500)).is_err());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_coalesces_burst_into_one_reload() {
+        let dir = scratch_dir("burst");
+        let path = dir.join("doc.md");
+        fs::write(&path, "# One").unwrap();
+
+        let reload_rx = start_watching(&path);
+
+        for i in 0..5 {
+            fs::write(&path, format!("# Version {}", i)).unwrap();
+        }
+        assert!(reload_rx.recv_timeout(Duration::from_secs(5)).is_ok());
+        assert!(
+            reload_rx.recv_timeout(Duration::from_millis(500)).is_err(),
+            "Burst of writes produced more than one reload"
+        );
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_signals_saves_of_unchanged_content() {
+        let dir = scratch_dir("same-bytes");
+        let path = dir.join("doc.md");
+        fs::write(&path, "# One").unwrap();
+
+        let reload_rx = start_watching(&path);
+
+        // Saving identical content still counts, so it can replace an editor's override
+        fs::write(&path, "# One").unwrap();
+        assert!(reload_rx.recv_timeout(Duration::from_secs(5)).is_ok());
+
+        fs::write(&path, "# Two").unwrap();
+        assert!(reload_rx.recv_timeout(Duration::from_secs(5)).is_ok());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_stops_when_stop_sender_dropped() {
+        let dir = scratch_dir("stop");
+        let path = dir.join("doc.md");
+        fs::write(&path, "# One").unwrap();
+
+        let (reload_tx, reload_rx) = unbounded();
+        let (stop_tx, stop_rx) = unbounded();
+        let handle = std::thread::spawn(move || {
+            watch_file_until(path, reload_tx, DEFAULT_DEBOUNCE, stop_rx).is_ok()
+        });
+
+        drop(stop_tx);
+        assert!(handle.join().unwrap());
+        // The watcher's sender is gone once it has stopped
+        assert!(reload_rx.recv().is_err());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_tree_signals_new_files_only() {
+        let dir = scratch_dir("tree");
+        fs::create_dir_all(dir.join("guide")).unwrap();
+        fs::write(dir.join("README.md"), "# Readme").unwrap();
+
+        let (change_tx, change_rx) = unbounded();
+        let root = dir.clone();
+        std::thread::spawn(move || {
+            let _ = watch_tree(root, change_tx, DEFAULT_DEBOUNCE);
+        });
+        std::thread::sleep(Duration::from_millis(300));
+
+        // Editing an existing file in place is not a structural change
+        fs::OpenOptions::new()
+            .append(true)
+            .open(dir.join("README.md"))
+            .unwrap()
+            .write_all(b"\nMore")
+            .unwrap();
+        assert!(change_rx.recv_timeout(Duration::from_millis(500)).is_err());
+
+        fs::write(dir.join("guide").join("setup.md"), "# Setup").unwrap();
+        assert!(change_rx.recv_timeout(Duration::from_secs(5)).is_ok());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}